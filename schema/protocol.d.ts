@@ -0,0 +1,125 @@
+// Generated from the Rust protocol types, protocol version 1. Do not edit.
+
+export type GameRequest = { action: GameRequestAction, card?: string, difficulty?: BotDifficulty, 
+/**
+ * Echoed in the reply to this request.
+ */
+request_id?: string, version?: number, 
+/**
+ * Name of the player a host action applies to.
+ */
+player?: string, rules?: RuleSet, 
+/**
+ * Chat line to send.
+ */
+text?: string, emote?: Emote, };
+
+export type GameRequestAction = "start_game" | "add_bot" | "rematch" | "draw" | "take_bin" | "discard" | "close" | "ack" | "sync" | "ready" | "unready" | "kick" | "transfer_host" | "lock_lobby" | "unlock_lobby" | "update_rules" | "chat" | "emote";
+
+export type BotDifficulty = "easy" | "medium" | "hard";
+
+export type Emote = "thumbs_up" | "laugh" | "wow" | "sad" | "angry" | "good_game";
+
+export type ServerEnvelope = { status: Status, } & ({ "message_type": "session", data: SessionData, } | { "message_type": "player_join", data: PlayerInfoData, message: string, } | { "message_type": "player_left", data: PlayerInfoData, message: string, } | { "message_type": "lobby_settings", data: PlayerInfoData, message: string, } | { "message_type": "reply", request_id: string | null, error: ErrorData | null, } | { "message_type": "game_event", data: GameData, } | { "message_type": "game_delta", data: GameDeltaData, } | { "message_type": "end_game", data: EndGameData, } | { "message_type": "match_score", data: MatchScoreData, } | { "message_type": "rematch", data: RematchData, } | { "message_type": "lobby_list", data: Array<LobbySummary>, } | { "message_type": "lobby_update", data: LobbySummary, } | { "message_type": "lobby_removed", data: LobbyRemovedData, } | { "message_type": "queue_status", data: QueueData, } | { "message_type": "chat", data: ChatLine, } | { "message_type": "chat_history", data: Array<ChatLine>, } | { "message_type": "game_closed", message: string, } | { "message_type": "server_restarting", message: string, resumable: boolean, });
+
+export type Status = "success" | "failed";
+
+export type ServerMessage = { "message_type": "session", data: SessionData, } | { "message_type": "player_join", data: PlayerInfoData, message: string, } | { "message_type": "player_left", data: PlayerInfoData, message: string, } | { "message_type": "lobby_settings", data: PlayerInfoData, message: string, } | { "message_type": "reply", request_id: string | null, error: ErrorData | null, } | { "message_type": "game_event", data: GameData, } | { "message_type": "game_delta", data: GameDeltaData, } | { "message_type": "end_game", data: EndGameData, } | { "message_type": "match_score", data: MatchScoreData, } | { "message_type": "rematch", data: RematchData, } | { "message_type": "lobby_list", data: Array<LobbySummary>, } | { "message_type": "lobby_update", data: LobbySummary, } | { "message_type": "lobby_removed", data: LobbyRemovedData, } | { "message_type": "queue_status", data: QueueData, } | { "message_type": "chat", data: ChatLine, } | { "message_type": "chat_history", data: Array<ChatLine>, } | { "message_type": "game_closed", message: string, } | { "message_type": "server_restarting", message: string, resumable: boolean, };
+
+export type SessionData = { player_id: string, token: string, protocol_version: number, };
+
+export type PlayerInfoData = { players: Array<PlayerData>, spectators: number, rules: RuleSet, 
+/**
+ * Name of the host.
+ */
+host: string | null, locked: boolean, 
+/**
+ * Names of the players ready to start, bots included.
+ */
+ready: Array<string>, 
+/**
+ * Unix timestamp in milliseconds at which the game starts on its own.
+ */
+starts_at: number | null, };
+
+export type PlayerData = { name: string, is_bot: boolean, hand: Array<string>, bin: Array<string>, };
+
+export type GameData = { version: number, player_id: string, 
+/**
+ * `None` for a viewer without a seat in the current round.
+ */
+player_pos: number | null, num_of_players: number, card_left: number, current_turn: number, current_phase: GamePhase, 
+/**
+ * Unix timestamp in milliseconds at which the current turn is played automatically.
+ */
+turn_deadline: number | null, rules: RuleSet, event: GameEvent, players: Array<PlayerData>, };
+
+export type GameDeltaData = { base_version: number, version: number, card_left: number, current_turn: number, current_phase: GamePhase, turn_deadline: number | null, event: GameEvent, 
+/**
+ * Only the seats whose hand or bin changed.
+ */
+players: Array<PlayerDelta>, };
+
+export type PlayerDelta = { seat: number, hand?: Array<string>, bin?: Array<string>, };
+
+export type GamePhase = "game_ended" | "p1" | "p2";
+
+export type RuleSet = { hand_size: number, close_threshold: number, ace_value: number, 
+/**
+ * Value of Jack, Queen and King.
+ */
+face_card_value: number, max_players: number, allow_close: boolean, 
+/**
+ * Seconds a player has to finish their turn before the server plays it for them, `None` disables the timer.
+ */
+turn_timeout_secs: number | null, 
+/**
+ * Consecutive timed out turns after which the player loses their seat, `None` never kicks.
+ */
+max_timeouts: number | null, 
+/**
+ * Seconds between every seated player being ready and the game starting on its own, `None` waits for the host.
+ */
+start_countdown_secs: number | null, };
+
+export type GameEvent = { event_type: GameEventType, from: number | null, to: number | null, };
+
+export type GameEventType = "game_start" | "draw" | "take_bin" | "discard" | "close" | "reconnect" | "watch" | "sync";
+
+export type EndGameData = { winner_name: string | null, players: Array<EndGameScores>, };
+
+export type EndGameScores = { name: string, score: number, hand: Array<string>, };
+
+export type MatchScoreData = { rounds_played: number, rounds: number, target_score: number | null, finished: boolean, winner_name: string | null, players: Array<MatchScoreEntry>, };
+
+export type MatchScoreEntry = { name: string, total: number, eliminated: boolean, };
+
+export type RematchData = { votes: Array<string>, needed: number, accepted: boolean, };
+
+export type LobbySummary = { game_id: string, players: number, max_players: number, password_protected: boolean, rounds: number, rules: RuleSet, 
+/**
+ * Unix timestamp in milliseconds.
+ */
+created_at: number, };
+
+export type LobbyRemovedData = { game_id: string, };
+
+export type QueueData = { table_size: number, 
+/**
+ * Players waiting for this table size, including the receiver.
+ */
+waiting: number, 
+/**
+ * Seconds after joining the queue at which the remaining seats are filled with bots.
+ */
+timeout_secs: number, };
+
+export type ChatLine = { name: string, text: string | null, emote: Emote | null, 
+/**
+ * Unix timestamp in milliseconds.
+ */
+sent_at: number, };
+
+export type ErrorData = { code: ErrorCode, message: string, };
+
+export type ErrorCode = "malformed_request" | "not_seated" | "game_not_started" | "game_already_started" | "game_not_finished" | "game_full" | "missing_card" | "unknown_card" | "missing_version" | "not_host" | "players_not_ready" | "missing_text" | "message_too_long" | "message_rejected" | "missing_emote" | "rate_limited" | "missing_player" | "unknown_player" | "invalid_target" | "missing_rules" | "invalid_rules" | "not_your_turn" | "invalid_move" | "invalid_player" | "card_not_in_hand" | "score_too_low" | "close_not_allowed" | "empty_bin";
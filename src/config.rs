@@ -1,10 +1,25 @@
 use dotenvy::dotenv;
 use std::env;
+use uuid::Uuid;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Config {
     pub server_address: String,
     pub allowed_origin: String,
+    pub session_secret: String,
+    /// Whether `session_secret` was configured, so session tokens outlive a restart.
+    pub fixed_session_secret: bool,
+    pub admin_token: Option<String>,
+    pub reconnect_grace_secs: u64,
+    pub matchmaking_timeout_secs: u64,
+    pub chat_blocked_words: Vec<String>,
+    pub gc_interval_secs: u64,
+    pub idle_lobby_ttl_secs: u64,
+    pub abandoned_game_ttl_secs: u64,
+    pub finished_game_ttl_secs: u64,
+    pub storage_path: Option<String>,
+    pub node_id: String,
+    pub local_cluster_addresses: Vec<String>,
 }
 
 impl Config {
@@ -15,7 +30,69 @@ impl Config {
 
         let server_address = env::var("SERVER_ADDRESS").expect("SERVER_ADDRESS is not set");
         let allowed_origin = env::var("ALLOWED_ORIGIN").unwrap_or_else(|_| "*".to_string());
+        // Without a fixed secret, session tokens only stay valid until the next restart.
+        let fixed_session_secret = env::var("SESSION_SECRET").is_ok();
+        let session_secret = env::var("SESSION_SECRET").unwrap_or_else(|_| Uuid::new_v4().to_string());
+        // Requests carrying it as a bearer token may pick the deal seed of a new game, unset allows no one.
+        let admin_token = env::var("ADMIN_TOKEN").ok().filter(|token| !token.is_empty());
+        let reconnect_grace_secs = env::var("RECONNECT_GRACE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30);
+        // Quick-match players waiting longer than this get the remaining seats filled with bots.
+        let matchmaking_timeout_secs = env::var("MATCHMAKING_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30);
+        // Comma-separated words masked in chat lines.
+        let chat_blocked_words = env::var("CHAT_BLOCKED_WORDS")
+            .map(|v| v.split(',').map(|word| word.trim().to_string()).collect())
+            .unwrap_or_default();
+        // Expired games are looked for this often and removed once untouched for their TTL.
+        let gc_interval_secs = env::var("GC_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60);
+        // A zero interval would make the collector's timer panic.
+        assert!(gc_interval_secs > 0, "GC_INTERVAL_SECS must be at least 1");
+        let idle_lobby_ttl_secs = env::var("IDLE_LOBBY_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1800);
+        let abandoned_game_ttl_secs = env::var("ABANDONED_GAME_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(600);
+        let finished_game_ttl_secs = env::var("FINISHED_GAME_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(600);
+        // Games are only kept in memory unless a storage file is configured.
+        let storage_path = env::var("STORAGE_PATH").ok();
+        // Comma-separated addresses of extra nodes run in this process, sharing an in-memory cluster backend.
+        let local_cluster_addresses = env::var("LOCAL_CLUSTER_ADDRESSES")
+            .map(|v| v.split(',').map(|address| address.trim().to_string()).filter(|address| !address.is_empty()).collect())
+            .unwrap_or_default();
+        // Games are hosted by the node that created them, and a node only takes its stored games
+        // back after a restart under the same id. The listen address stays put across restarts too.
+        let node_id = env::var("NODE_ID").ok().filter(|node_id| !node_id.is_empty()).unwrap_or_else(|| server_address.clone());
 
-        Self { server_address, allowed_origin }
+        Self {
+            server_address,
+            allowed_origin,
+            session_secret,
+            fixed_session_secret,
+            admin_token,
+            reconnect_grace_secs,
+            matchmaking_timeout_secs,
+            chat_blocked_words,
+            gc_interval_secs,
+            idle_lobby_ttl_secs,
+            abandoned_game_ttl_secs,
+            finished_game_ttl_secs,
+            storage_path,
+            node_id,
+            local_cluster_addresses,
+        }
     }
 }
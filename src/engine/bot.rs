@@ -0,0 +1,249 @@
+use crate::engine::card::Card;
+use crate::engine::game::{score_cards, Game, GamePhase};
+use crate::engine::rules::RuleSet;
+use rand::seq::IndexedRandom;
+use rand::{rng, Rng};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BotDifficulty {
+    Easy,
+    #[default]
+    Medium,
+    Hard,
+}
+
+impl BotDifficulty {
+    pub fn strategy(&self) -> &'static dyn BotStrategy {
+        match self {
+            BotDifficulty::Easy => &RandomBot,
+            BotDifficulty::Medium => &GreedyBot,
+            BotDifficulty::Hard => &LookaheadBot,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BotAction {
+    Draw,
+    TakeBin,
+    Discard(Card),
+    Close(Card),
+}
+
+/// What a bot is allowed to see of the table: its own hand, every bin pile and the deck size.
+pub struct BotView<'a> {
+    pub player_pos: usize,
+    pub phase: GamePhase,
+    pub hand: &'a [Card],
+    pub bins: Vec<&'a [Card]>,
+    pub card_left: u8,
+    pub rules: &'a RuleSet,
+}
+
+impl<'a> BotView<'a> {
+    pub fn new(game: &'a Game, player_uuid: &Uuid) -> Option<BotView<'a>> {
+        let player_pos = game.player_pos(player_uuid)?;
+        Some(BotView {
+            player_pos,
+            phase: game.phase.clone(),
+            hand: &game.players[player_pos].hand,
+            bins: game.players.iter().map(|player| player.bin.as_slice()).collect(),
+            card_left: game.card_left(),
+            rules: &game.rules,
+        })
+    }
+
+    pub fn own_bin(&self) -> &[Card] {
+        self.bins[self.player_pos]
+    }
+
+    pub fn can_close(&self, score: i16) -> bool {
+        self.rules.allow_close && score >= self.rules.close_threshold
+    }
+
+    /// Cards that are neither in our hand nor visible on a bin pile, i.e. in the deck or in an opponent's hand.
+    pub fn unseen_cards(&self) -> Vec<Card> {
+        Game::ordered_deck().into_iter().filter(|card| {
+            !self.hand.contains(card) && !self.bins.iter().any(|bin| bin.contains(card))
+        }).collect()
+    }
+}
+
+pub trait BotStrategy: Send + Sync {
+    /// Called in `GamePhase::P1`, must return `Draw` or `TakeBin`.
+    fn pick_up(&self, view: &BotView) -> BotAction;
+    /// Called in `GamePhase::P2`, must return `Discard` or `Close`.
+    fn put_down(&self, view: &BotView) -> BotAction;
+
+    fn decide(&self, view: &BotView) -> Option<BotAction> {
+        match view.phase {
+            GamePhase::P1 => Some(self.pick_up(view)),
+            GamePhase::P2 => Some(self.put_down(view)),
+            GamePhase::GameEnded => None,
+        }
+    }
+}
+
+/// Keeps whichever hand scores best and closes as soon as the score allows it.
+pub struct GreedyBot;
+
+impl BotStrategy for GreedyBot {
+    fn pick_up(&self, view: &BotView) -> BotAction {
+        let top = match view.own_bin().last() {
+            Some(card) => card,
+            None => return BotAction::Draw,
+        };
+
+        let mut hand = view.hand.to_vec();
+        hand.push(top.clone());
+        let (discard, score) = best_discard(&hand, view.rules);
+        // Only worth taking if the bin card is not the one we would throw away right after.
+        if discard != hand.len() - 1 && score > hand_score(view.hand, view.rules) {
+            BotAction::TakeBin
+        } else {
+            BotAction::Draw
+        }
+    }
+
+    fn put_down(&self, view: &BotView) -> BotAction {
+        let (discard, score) = best_discard(view.hand, view.rules);
+        let card = view.hand[discard].clone();
+        if view.can_close(score) {
+            BotAction::Close(card)
+        } else {
+            BotAction::Discard(card)
+        }
+    }
+}
+
+/// Picks legal moves at random, closing only when the random discard happens to allow it.
+pub struct RandomBot;
+
+impl BotStrategy for RandomBot {
+    fn pick_up(&self, view: &BotView) -> BotAction {
+        if !view.own_bin().is_empty() && rng().random_bool(0.5) {
+            BotAction::TakeBin
+        } else {
+            BotAction::Draw
+        }
+    }
+
+    fn put_down(&self, view: &BotView) -> BotAction {
+        let card = view.hand.choose(&mut rng()).unwrap().clone();
+        let mut rest = view.hand.to_vec();
+        rest.retain(|c| *c != card);
+        if view.can_close(hand_score(&rest, view.rules)) {
+            BotAction::Close(card)
+        } else {
+            BotAction::Discard(card)
+        }
+    }
+}
+
+/// Compares the bin card against the expected value of a draw over every unseen card,
+/// and discards towards the hand with the best expected next draw.
+pub struct LookaheadBot;
+
+impl BotStrategy for LookaheadBot {
+    fn pick_up(&self, view: &BotView) -> BotAction {
+        let top = match view.own_bin().last() {
+            Some(card) => card,
+            None => return BotAction::Draw,
+        };
+
+        let unseen = view.unseen_cards();
+        let mut hand = view.hand.to_vec();
+        hand.push(top.clone());
+        let bin_value = turn_value(&hand, &unseen, view);
+
+        let draw_value = unseen.iter().map(|card| {
+            let mut hand = view.hand.to_vec();
+            hand.push(card.clone());
+            let rest: Vec<Card> = unseen.iter().filter(|c| *c != card).cloned().collect();
+            turn_value(&hand, &rest, view)
+        }).sum::<f64>() / unseen.len().max(1) as f64;
+
+        if bin_value > draw_value {
+            BotAction::TakeBin
+        } else {
+            BotAction::Draw
+        }
+    }
+
+    fn put_down(&self, view: &BotView) -> BotAction {
+        let (discard, score) = best_discard(view.hand, view.rules);
+        if view.can_close(score) {
+            return BotAction::Close(view.hand[discard].clone());
+        }
+        // The deck is empty so this discard ends the game, only the final score counts.
+        if view.card_left == 0 {
+            return BotAction::Discard(view.hand[discard].clone());
+        }
+
+        // Not closing yet, so keep the hand with the best prospects for the next draw
+        // and prefer throwing low cards that help the next player less.
+        let unseen = view.unseen_cards();
+        let mut best: Option<(usize, f64)> = None;
+        for i in 0..view.hand.len() {
+            let mut rest = view.hand.to_vec();
+            rest.remove(i);
+            let expected = expected_draw_score(&rest, &unseen, view.rules);
+            let better = match best {
+                None => true,
+                Some((j, best_expected)) => expected > best_expected
+                    || (expected == best_expected && view.hand[i].points_with(view.rules) < view.hand[j].points_with(view.rules)),
+            };
+            if better {
+                best = Some((i, expected));
+            }
+        }
+        BotAction::Discard(view.hand[best.unwrap().0].clone())
+    }
+}
+
+/// How good a hand is at the end of a pick up: closing beats everything,
+/// otherwise it is worth the best expected score of the following draw.
+fn turn_value(hand: &[Card], unseen: &[Card], view: &BotView) -> f64 {
+    let (_, score) = best_discard(hand, view.rules);
+    if view.can_close(score) {
+        return 100.0 + score as f64;
+    }
+    (0..hand.len()).map(|i| {
+        let mut rest = hand.to_vec();
+        rest.remove(i);
+        expected_draw_score(&rest, unseen, view.rules)
+    }).fold(f64::MIN, f64::max)
+}
+
+/// Average best score after drawing any one of `unseen` into `hand` and discarding optimally.
+fn expected_draw_score(hand: &[Card], unseen: &[Card], rules: &RuleSet) -> f64 {
+    if unseen.is_empty() {
+        return hand_score(hand, rules) as f64;
+    }
+    unseen.iter().map(|card| {
+        let mut hand = hand.to_vec();
+        hand.push(card.clone());
+        best_discard(&hand, rules).1 as f64
+    }).sum::<f64>() / unseen.len() as f64
+}
+
+pub fn hand_score(hand: &[Card], rules: &RuleSet) -> i16 {
+    score_cards(hand.iter(), rules)
+}
+
+/// Index of the card whose removal leaves the best scoring hand, and that score.
+pub fn best_discard(hand: &[Card], rules: &RuleSet) -> (usize, i16) {
+    let mut best = (0, i16::MIN);
+    for i in 0..hand.len() {
+        let rest = hand.iter().enumerate().filter(|(j, _)| *j != i).map(|(_, card)| card);
+        let score = score_cards(rest, rules);
+        if score > best.1 {
+            best = (i, score);
+        }
+    }
+    best
+}
@@ -1,4 +1,6 @@
+use crate::engine::rules::RuleSet;
 use serde::{Serialize, Deserialize};
+use std::fmt;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Suit {
@@ -32,9 +34,14 @@ pub struct Card {
 }
 
 impl Card {
+    #[allow(dead_code)]
     pub fn points(&self) -> u16 {
+        self.points_with(&RuleSet::default())
+    }
+
+    pub fn points_with(&self, rules: &RuleSet) -> u16 {
         match self.rank {
-            Rank::Ace => {11}
+            Rank::Ace => {rules.ace_value}
             Rank::Two => {2}
             Rank::Three => {3}
             Rank::Four => {4}
@@ -44,41 +51,15 @@ impl Card {
             Rank::Eight => {8}
             Rank::Nine => {9}
             Rank::Ten => {10}
-            Rank::Jack => {10}
-            Rank::Queen => {10}
-            Rank::King => {10}
+            Rank::Jack => {rules.face_card_value}
+            Rank::Queen => {rules.face_card_value}
+            Rank::King => {rules.face_card_value}
         }
     }
 
-    pub fn to_string(&self) -> String {
-        let suit = match self.suit {
-            Suit::Hearts => "H",
-            Suit::Diamonds => "D",
-            Suit::Clubs => "C",
-            Suit::Spades => "S",
-        };
-
-        let rank = match self.rank {
-            Rank::Ace => "A",
-            Rank::Two => "2",
-            Rank::Three => "3",
-            Rank::Four => "4",
-            Rank::Five => "5",
-            Rank::Six => "6",
-            Rank::Seven => "7",
-            Rank::Eight => "8",
-            Rank::Nine => "9",
-            Rank::Ten => "X",
-            Rank::Jack => "J",
-            Rank::Queen => "Q",
-            Rank::King => "K",
-        };
-
-        format!("{}{}", suit, rank)
-    }
-
     pub fn from_string(input: &str) -> Option<Self> {
-        if input.len() != 2 {
+        // Checked by character, so a multi-byte one is refused rather than split in the middle.
+        if input.chars().count() != 2 || !input.is_char_boundary(1) {
             return None;
         }
 
@@ -110,4 +91,33 @@ impl Card {
 
         Some(Card { suit, rank })
     }
+}
+
+impl fmt::Display for Card {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let suit = match self.suit {
+            Suit::Hearts => "H",
+            Suit::Diamonds => "D",
+            Suit::Clubs => "C",
+            Suit::Spades => "S",
+        };
+
+        let rank = match self.rank {
+            Rank::Ace => "A",
+            Rank::Two => "2",
+            Rank::Three => "3",
+            Rank::Four => "4",
+            Rank::Five => "5",
+            Rank::Six => "6",
+            Rank::Seven => "7",
+            Rank::Eight => "8",
+            Rank::Nine => "9",
+            Rank::Ten => "X",
+            Rank::Jack => "J",
+            Rank::Queen => "Q",
+            Rank::King => "K",
+        };
+
+        write!(f, "{}{}", suit, rank)
+    }
 }
\ No newline at end of file
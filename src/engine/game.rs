@@ -1,6 +1,9 @@
 use crate::engine::card::{Card, Rank, Suit};
-use rand::rng;
+use crate::engine::replay::{GameAction, GameLog};
+use crate::engine::rules::RuleSet;
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
+use rand::{rng, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 use std::cmp::{max, PartialEq};
 use uuid::Uuid;
@@ -12,8 +15,17 @@ pub enum GameError {
     #[allow(dead_code)]
     InvalidPlayer,
     InvalidTurn,
+    /// The move is not allowed in the current phase.
     InvalidMove,
+    NotYourTurn,
     CardNotFound,
+    /// Closing needs a score of at least the rules' `close_threshold`.
+    ScoreTooLow,
+    /// The rules disable closing.
+    CloseNotAllowed,
+    EmptyBin,
+    /// A replayed move does not match the recorded one.
+    LogMismatch,
 }
 
 #[allow(dead_code)]
@@ -28,6 +40,7 @@ pub struct EndPhaseResponse {
     pub next_turn: u8,
     pub winner: Option<Player>
 }
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum GamePhase {
@@ -45,15 +58,42 @@ pub struct Game {
     pub deck: Vec<Card>,
     pub current_turn: usize,
     pub phase: GamePhase,
+    #[serde(default)]
+    pub rules: RuleSet,
+    /// Deal and moves so far, enough to replay the game.
+    #[serde(default)]
+    pub log: GameLog,
+    /// Seed the deck was shuffled with, `None` when the deck was given to `with_deck`.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Bumped on every recorded move, so clients can tell which state an update applies to.
+    #[serde(default)]
+    pub version: u64,
 }
 
 impl Game {
-    pub fn new( players_uuid: Vec<Uuid>) -> Game {
+    pub fn new( players_uuid: Vec<Uuid>, rules: RuleSet) -> Game {
+        Self::from_seed(players_uuid, rules, rng().random())
+    }
+
+    /// Deal a deck shuffled from `seed`, the same seed and seats always get the same deal.
+    pub fn from_seed(players_uuid: Vec<Uuid>, rules: RuleSet, seed: u64) -> Game {
+        let mut game = Self::with_deck(players_uuid, rules, Self::create_deck(seed));
+        game.seed = Some(seed);
+        game
+    }
 
-        let mut deck = Self::create_deck();
+    /// Deal from `deck` as given, cards are taken from the end.
+    pub fn with_deck(players_uuid: Vec<Uuid>, rules: RuleSet, mut deck: Vec<Card>) -> Game {
+        let log = GameLog {
+            seats: players_uuid.clone(),
+            rules: rules.clone(),
+            deck: deck.clone(),
+            actions: vec![],
+        };
         let players: Vec<Player> = players_uuid.iter().map(|&uuid| {
             let mut hand = vec![];
-            for _ in 0..4 {
+            for _ in 0..rules.hand_size {
                 if let Some(card) = deck.pop() {
                     hand.push(card);
                 } else {
@@ -73,26 +113,32 @@ impl Game {
             deck,
             current_turn: 0,
             phase: GamePhase::P1,
+            rules,
+            log,
+            seed: None,
+            version: 0,
         }
     }
 
     pub fn close(&mut self, player_uuid: &Uuid, card: Card) -> Result<EndPhaseResponse, GameError> {
-        if self.players[self.current_turn].id != *player_uuid || self.phase != GamePhase::P2 {
-            return Err(GameError::InvalidMove);
+        if !self.rules.allow_close {
+            return Err(GameError::CloseNotAllowed);
         }
+        self.check_turn(player_uuid, GamePhase::P2)?;
 
         if let Err(GameError::CardNotFound) = self.remove_card(&card) {
             return Err(GameError::CardNotFound);
         }
 
-        if self.players[self.current_turn].score() < MINIMUM_CLOSE_SCORE {
+        if self.players[self.current_turn].score_with(&self.rules) < self.rules.close_threshold {
             self.players[self.current_turn].hand.push(card);
-            return Err(GameError::InvalidMove);
+            return Err(GameError::ScoreTooLow);
         }
 
         self.current_turn = (self.current_turn + 1) % self.players.len();
 
         self.phase = GamePhase::GameEnded;
+        self.record(GameAction::Close { player_id: *player_uuid, card });
         Ok(EndPhaseResponse {
             next_turn: self.current_turn as u8,
             status: Some(GameStatus::Ended),
@@ -101,9 +147,13 @@ impl Game {
     }
 
     pub fn discard(&mut self, player_uuid: &Uuid, card: Card) -> Result<EndPhaseResponse, GameError> {
-        if self.players[self.current_turn].id != *player_uuid || self.phase != GamePhase::P2 {
-            return Err(GameError::InvalidMove);
-        }
+        let response = self.discard_card(player_uuid, card.clone())?;
+        self.record(GameAction::Discard { player_id: *player_uuid, card });
+        Ok(response)
+    }
+
+    fn discard_card(&mut self, player_uuid: &Uuid, card: Card) -> Result<EndPhaseResponse, GameError> {
+        self.check_turn(player_uuid, GamePhase::P2)?;
 
         if let Err(GameError::CardNotFound) = self.remove_card(&card) {
             return Err(GameError::CardNotFound);
@@ -111,7 +161,7 @@ impl Game {
 
         self.current_turn = (self.current_turn + 1) % self.players.len();
 
-        if self.deck.len() > 0 {
+        if !self.deck.is_empty() {
             self.phase = GamePhase::P1;
             self.players[self.current_turn].bin.push(card);
             Ok(EndPhaseResponse {
@@ -130,24 +180,21 @@ impl Game {
     }
     pub fn take_bin(&mut self, player_uuid: &Uuid) -> Result<(), GameError> {
         // println!("Taking bin: {:?}", self.deck);
-        if self.players[self.current_turn].id != *player_uuid || self.phase != GamePhase::P1 {
-            return Err(GameError::InvalidMove);
-        }
+        self.check_turn(player_uuid, GamePhase::P1)?;
 
         let card = match self.players[self.current_turn].bin.pop() {
             Some(card) => card,
-            None => return Err(GameError::InvalidMove),
+            None => return Err(GameError::EmptyBin),
         };
 
-        self.players[self.current_turn].hand.push(card);
+        self.players[self.current_turn].hand.push(card.clone());
         self.phase = GamePhase::P2;
+        self.record(GameAction::TakeBin { player_id: *player_uuid, card });
         Ok(())
     }
 
     pub fn draw(&mut self, player_uuid: &Uuid) -> Result<(), GameError>  {
-        if self.players[self.current_turn].id != *player_uuid || self.phase != GamePhase::P1  {
-            return Err(GameError::InvalidMove);
-        }
+        self.check_turn(player_uuid, GamePhase::P1)?;
 
         let card = match self.deck.pop() {
             Some(card) => card,
@@ -155,8 +202,9 @@ impl Game {
         };
 
         if let Some(current_player) = self.players.get_mut(self.current_turn) {
-            current_player.hand.push(card);
+            current_player.hand.push(card.clone());
             self.phase = GamePhase::P2;
+            self.record(GameAction::Draw { player_id: *player_uuid, card });
             Ok(())
         } else {
             self.deck.push(card);
@@ -164,13 +212,24 @@ impl Game {
         }
     }
 
+    /// Take a player out of the round. When it is their turn, they discard first so the turn moves
+    /// on. Once the round ended nothing is left to play, the player is only dropped.
     pub fn remove_player(&mut self, player_uuid: &Uuid) -> Result<(), GameError> {
         if let Some(index) = self.players.iter().position(|c| c.id == *player_uuid) {
-            if self.current_turn == index {
+            if self.current_turn == index && self.phase != GamePhase::GameEnded {
+                let card = self.players[index].hand.first().cloned().ok_or(GameError::CardNotFound)?;
                 self.phase = GamePhase::P2;
-                self.discard(player_uuid, self.players[index].hand[0].clone())?;
+                self.discard_card(player_uuid, card)?;
             }
             self.players.remove(index);
+            self.record(GameAction::Leave { player_id: *player_uuid });
+            // Seats after the removed one shift down by one.
+            if index < self.current_turn {
+                self.current_turn -= 1;
+            }
+            if self.current_turn >= self.players.len() {
+                self.current_turn = 0;
+            }
         }
 
         Ok(())
@@ -178,7 +237,7 @@ impl Game {
 
     #[allow(dead_code)]
     pub fn scores(&self) -> Vec<i16> {
-        self.players.iter().map(|player: &Player| {player.score()}).collect()
+        self.players.iter().map(|player: &Player| {player.score_with(&self.rules)}).collect()
     }
 
     #[allow(dead_code)]
@@ -188,7 +247,7 @@ impl Game {
             None => return Err(GameError::InvalidPlayer)
         };
 
-        Ok(self.players[index].score())
+        Ok(self.players[index].score_with(&self.rules))
     }
 
     pub fn winner(&self) -> Option<Player> {
@@ -198,7 +257,7 @@ impl Game {
         let mut winner = None;
         let mut max_score = 0;
         for player in &self.players {
-            let score = player.score();
+            let score = player.score_with(&self.rules);
             if max_score < score {
                 winner = Some(player.clone());
                 max_score = score;
@@ -223,6 +282,21 @@ impl Game {
         self.deck.len() as u8
     }
 
+    fn record(&mut self, action: GameAction) {
+        self.log.actions.push(action);
+        self.version += 1;
+    }
+
+    fn check_turn(&self, player_uuid: &Uuid, phase: GamePhase) -> Result<(), GameError> {
+        if self.players[self.current_turn].id != *player_uuid {
+            return Err(GameError::NotYourTurn);
+        }
+        if self.phase != phase {
+            return Err(GameError::InvalidMove);
+        }
+        Ok(())
+    }
+
     fn remove_card(&mut self, card: &Card) -> Result<(), GameError> {
         let index = match self.players[self.current_turn].hand.iter().position(|c| c == card) {
             Some(i) => i,
@@ -233,7 +307,14 @@ impl Game {
         Ok(())
     }
 
-    fn create_deck() -> Vec<Card> {
+    fn create_deck(seed: u64) -> Vec<Card> {
+        let mut cards = Self::ordered_deck();
+        cards.shuffle(&mut StdRng::seed_from_u64(seed));
+        cards
+    }
+
+    /// Every card of the 52 card deck, unshuffled.
+    pub fn ordered_deck() -> Vec<Card> {
         let mut cards = Vec::with_capacity(52);
 
         for suit in [Suit::Hearts, Suit::Diamonds, Suit::Clubs, Suit::Spades].iter() {
@@ -248,7 +329,6 @@ impl Game {
                 })
             }
         }
-        cards.shuffle(&mut rng());
         cards
     }
 }
@@ -261,24 +341,33 @@ pub struct Player {
 }
 
 impl Player {
+    #[allow(dead_code)]
     pub fn score(&self) -> i16 {
+        self.score_with(&RuleSet::default())
+    }
 
-        let mut points:[u16;4] = [0, 0, 0, 0];
-        let mut max_point:u16 = 0;
-        for i in 0..4 {
-            let card = self.hand.get(i).unwrap();
-            let point = card.points();
-            let ip = match self.hand[i].suit {
-                Suit::Hearts => {0},
-                Suit::Diamonds => {1},
-                Suit::Clubs => {2},
-                Suit::Spades => {3},
-            };
-            points[ip] += point;
-            max_point = max(max_point, points[ip]);
-        }
-        ((max_point as i16) *2) - points.iter().sum::<u16>() as i16
+    /// Score of the first `hand_size` cards of the hand.
+    pub fn score_with(&self, rules: &RuleSet) -> i16 {
+        score_cards(self.hand.iter().take(rules.hand_size), rules)
+    }
+}
+
+/// The best suit's points minus the points of every other card.
+pub fn score_cards<'a>(cards: impl Iterator<Item = &'a Card>, rules: &RuleSet) -> i16 {
+    let mut points:[u16;4] = [0, 0, 0, 0];
+    let mut max_point:u16 = 0;
+    for card in cards {
+        let point = card.points_with(rules);
+        let ip = match card.suit {
+            Suit::Hearts => {0},
+            Suit::Diamonds => {1},
+            Suit::Clubs => {2},
+            Suit::Spades => {3},
+        };
+        points[ip] += point;
+        max_point = max(max_point, points[ip]);
     }
+    ((max_point as i16) *2) - points.iter().sum::<u16>() as i16
 }
 
 
@@ -0,0 +1,131 @@
+use crate::engine::game::Game;
+use crate::engine::rules::RuleSet;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// How long a match runs. The defaults play a single round, like a standalone game.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MatchSettings {
+    pub rounds: u8,
+    /// Ends the match early once a player's running total reaches it.
+    pub target_score: Option<i32>,
+    /// Drop the lowest running total after every round, as long as it is not tied.
+    pub eliminate_lowest: bool,
+    /// Deal every round from this seed, so tables sharing it get identical deals (duplicate play).
+    pub seed: Option<u64>,
+}
+
+impl Default for MatchSettings {
+    fn default() -> Self {
+        MatchSettings {
+            rounds: 1,
+            target_score: None,
+            eliminate_lowest: false,
+            seed: None,
+        }
+    }
+}
+
+impl MatchSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.rounds == 0 {
+            return Err("A match needs at least 1 round".to_string());
+        }
+        if self.target_score.is_some_and(|target| target <= 0) {
+            return Err("Target score must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// A series of `Game`s between the same players with a running total per player.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameMatch {
+    pub settings: MatchSettings,
+    /// Seating order, kept for rotating the starting player.
+    pub seats: Vec<Uuid>,
+    pub totals: HashMap<Uuid, i32>,
+    pub eliminated: Vec<Uuid>,
+    pub rounds_played: u8,
+}
+
+impl GameMatch {
+    pub fn new(players: Vec<Uuid>, settings: MatchSettings) -> GameMatch {
+        GameMatch {
+            settings,
+            totals: players.iter().map(|id| (*id, 0)).collect(),
+            seats: players,
+            eliminated: vec![],
+            rounds_played: 0,
+        }
+    }
+
+    pub fn active_players(&self) -> Vec<Uuid> {
+        self.seats.iter().filter(|id| !self.eliminated.contains(id)).cloned().collect()
+    }
+
+    /// Deal the next round to every active player, the first seat moves one place each round.
+    pub fn start_round(&self, rules: RuleSet) -> Game {
+        let mut players = self.active_players();
+        if !players.is_empty() {
+            let first = self.rounds_played as usize % players.len();
+            players.rotate_left(first);
+        }
+        match self.settings.seed {
+            Some(seed) => Game::from_seed(players, rules, seed.wrapping_add(self.rounds_played as u64)),
+            None => Game::new(players, rules),
+        }
+    }
+
+    /// Add the scores of a finished round to the totals, returns the players eliminated by it.
+    pub fn record_round(&mut self, game: &Game) -> Vec<Uuid> {
+        for player in &game.players {
+            *self.totals.entry(player.id).or_insert(0) += player.score_with(&game.rules) as i32;
+        }
+        self.rounds_played += 1;
+
+        let active = self.active_players();
+        if !self.settings.eliminate_lowest || active.len() <= 2 || self.is_over() {
+            return vec![];
+        }
+        let lowest = active.iter().map(|id| self.totals[id]).min().unwrap();
+        let lowest_players: Vec<Uuid> = active.into_iter().filter(|id| self.totals[id] == lowest).collect();
+        if lowest_players.len() != 1 {
+            return vec![];
+        }
+        self.eliminated.extend(lowest_players.iter().cloned());
+        lowest_players
+    }
+
+    pub fn remove_player(&mut self, player_uuid: &Uuid) {
+        self.seats.retain(|id| id != player_uuid);
+        self.totals.remove(player_uuid);
+        self.eliminated.retain(|id| id != player_uuid);
+    }
+
+    pub fn is_over(&self) -> bool {
+        let active = self.active_players();
+        self.rounds_played >= self.settings.rounds
+            || active.len() < 2
+            || self.settings.target_score.is_some_and(|target| {
+                active.iter().any(|id| self.totals[id] >= target)
+            })
+    }
+
+    /// Highest running total among active players, `None` on a tie or while the match is running.
+    pub fn winner(&self) -> Option<Uuid> {
+        if !self.is_over() {
+            return None;
+        }
+        let active = self.active_players();
+        let best = active.iter().map(|id| self.totals[id]).max()?;
+        let leaders: Vec<&Uuid> = active.iter().filter(|id| self.totals[id] == best).collect();
+        if leaders.len() == 1 {
+            Some(*leaders[0])
+        } else {
+            None
+        }
+    }
+}
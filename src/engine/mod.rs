@@ -1,4 +1,10 @@
 pub mod game;
 pub mod card;
+pub mod bot;
+pub mod rules;
+pub mod game_match;
+pub mod replay;
+#[cfg(test)]
+pub mod simulation;
 
 mod test;
\ No newline at end of file
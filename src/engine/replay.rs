@@ -0,0 +1,69 @@
+use crate::engine::card::Card;
+use crate::engine::game::{Game, GameError};
+use crate::engine::rules::RuleSet;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A move recorded by `Game`, with the card it involved.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum GameAction {
+    Draw { player_id: Uuid, card: Card },
+    TakeBin { player_id: Uuid, card: Card },
+    Discard { player_id: Uuid, card: Card },
+    Close { player_id: Uuid, card: Card },
+    /// The player's seat was vacated, discarding their first card if it was their turn.
+    Leave { player_id: Uuid },
+}
+
+/// Everything needed to play a game again: the deal and every move in order.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GameLog {
+    pub seats: Vec<Uuid>,
+    pub rules: RuleSet,
+    /// The shuffled deck before dealing, cards are dealt from the end.
+    pub deck: Vec<Card>,
+    pub actions: Vec<GameAction>,
+}
+
+/// Rebuild every intermediate state of a logged game, starting with the deal. Fails when a move
+/// is rejected or does not involve the recorded card.
+pub fn replay(log: &GameLog) -> Result<Vec<Game>, GameError> {
+    let mut game = Game::with_deck(log.seats.clone(), log.rules.clone(), log.deck.clone());
+    let mut states = vec![game.clone()];
+
+    for action in &log.actions {
+        match action {
+            GameAction::Draw { player_id, card } => {
+                game.draw(player_id)?;
+                check_picked_card(&game, card)?;
+            }
+            GameAction::TakeBin { player_id, card } => {
+                game.take_bin(player_id)?;
+                check_picked_card(&game, card)?;
+            }
+            GameAction::Discard { player_id, card } => {
+                game.discard(player_id, card.clone())?;
+            }
+            GameAction::Close { player_id, card } => {
+                game.close(player_id, card.clone())?;
+            }
+            GameAction::Leave { player_id } => {
+                if game.player_pos(player_id).is_none() {
+                    return Err(GameError::LogMismatch);
+                }
+                game.remove_player(player_id)?;
+            }
+        }
+        states.push(game.clone());
+    }
+
+    Ok(states)
+}
+
+fn check_picked_card(game: &Game, card: &Card) -> Result<(), GameError> {
+    if game.players[game.current_turn].hand.last() != Some(card) {
+        return Err(GameError::LogMismatch);
+    }
+    Ok(())
+}
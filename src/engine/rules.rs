@@ -0,0 +1,80 @@
+use crate::engine::game::{MAX_PLAYER, MINIMUM_CLOSE_SCORE};
+use serde::{Deserialize, Serialize};
+
+/// Highest value an ace or a face card may be given, so hand scores stay far from overflowing.
+pub const MAX_CARD_VALUE: u16 = 11;
+
+/// Longest turn timer a game may set, one hour.
+pub const MAX_TURN_TIMEOUT_SECS: u64 = 3600;
+
+/// Longest wait between everyone getting ready and the game starting on its own, ten minutes.
+pub const MAX_START_COUNTDOWN_SECS: u64 = 600;
+
+/// House rules a game is played with. Missing fields fall back to the standard rules.
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RuleSet {
+    pub hand_size: usize,
+    pub close_threshold: i16,
+    pub ace_value: u16,
+    /// Value of Jack, Queen and King.
+    pub face_card_value: u16,
+    pub max_players: usize,
+    pub allow_close: bool,
+    /// Seconds a player has to finish their turn before the server plays it for them, `None` disables the timer.
+    #[cfg_attr(test, ts(type = "number | null"))]
+    pub turn_timeout_secs: Option<u64>,
+    /// Consecutive timed out turns after which the player loses their seat, `None` never kicks.
+    pub max_timeouts: Option<u8>,
+    /// Seconds between every seated player being ready and the game starting on its own, `None` waits for the host.
+    #[cfg_attr(test, ts(type = "number | null"))]
+    pub start_countdown_secs: Option<u64>,
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        RuleSet {
+            hand_size: 4,
+            close_threshold: MINIMUM_CLOSE_SCORE,
+            ace_value: 11,
+            face_card_value: 10,
+            max_players: MAX_PLAYER,
+            allow_close: true,
+            turn_timeout_secs: None,
+            max_timeouts: None,
+            start_countdown_secs: None,
+        }
+    }
+}
+
+impl RuleSet {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.hand_size == 0 {
+            return Err("Hand size must be at least 1".to_string());
+        }
+        if self.max_players < 2 {
+            return Err("A game needs at least 2 players".to_string());
+        }
+        // Every seat gets dealt a hand and the deck still needs a card to draw.
+        if self.max_players.checked_mul(self.hand_size).is_none_or(|cards| cards >= 52) {
+            return Err("Not enough cards for this hand size and player count".to_string());
+        }
+        if self.ace_value > MAX_CARD_VALUE || self.face_card_value > MAX_CARD_VALUE {
+            return Err(format!("Card values must be at most {}", MAX_CARD_VALUE));
+        }
+        if self.turn_timeout_secs == Some(0) {
+            return Err("Turn timeout must be at least 1 second".to_string());
+        }
+        if self.turn_timeout_secs.is_some_and(|secs| secs > MAX_TURN_TIMEOUT_SECS) {
+            return Err(format!("Turn timeout must be at most {} seconds", MAX_TURN_TIMEOUT_SECS));
+        }
+        if self.max_timeouts == Some(0) {
+            return Err("Max timeouts must be at least 1".to_string());
+        }
+        if self.start_countdown_secs.is_some_and(|secs| secs > MAX_START_COUNTDOWN_SECS) {
+            return Err(format!("Start countdown must be at most {} seconds", MAX_START_COUNTDOWN_SECS));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use crate::engine::bot::{BotAction, BotStrategy, BotView};
+use crate::engine::game::{Game, GamePhase};
+use crate::engine::rules::RuleSet;
+use uuid::Uuid;
+
+/// Upper bound on moves in one game, a 52 card deck can never need this many.
+const MAX_MOVES: usize = 1000;
+
+#[derive(Debug, Default)]
+pub struct SimulationReport {
+    pub games: usize,
+    pub draws: usize,
+    pub wins: Vec<usize>,
+    pub total_scores: Vec<i64>,
+    /// Games where a bot's move was rejected, the card count changed or the game never ended.
+    pub rule_violations: usize,
+}
+
+impl SimulationReport {
+    pub fn win_rate(&self, seat: usize) -> f64 {
+        self.wins[seat] as f64 / self.games as f64
+    }
+
+    pub fn average_score(&self, seat: usize) -> f64 {
+        self.total_scores[seat] as f64 / self.games as f64
+    }
+}
+
+/// Play `games` bot-vs-bot games headlessly. Seats rotate every game so no strategy
+/// keeps the first move, and every deal is replayed once per rotation so each strategy plays
+/// it from every seat. Deals come from `seed`, results are reported per entry of `strategies`.
+pub fn simulate(strategies: &[&dyn BotStrategy], rules: &RuleSet, games: usize, seed: u64) -> SimulationReport {
+    let mut report = SimulationReport {
+        wins: vec![0; strategies.len()],
+        total_scores: vec![0; strategies.len()],
+        ..Default::default()
+    };
+    let ids: Vec<Uuid> = strategies.iter().map(|_| Uuid::new_v4()).collect();
+
+    for round in 0..games {
+        let mut seating = ids.clone();
+        seating.rotate_left(round % ids.len());
+        let deal = seed.wrapping_add((round / ids.len()) as u64);
+        let mut game = Game::from_seed(seating, rules.clone(), deal);
+
+        report.games += 1;
+        if !play_out(&mut game, strategies, &ids) {
+            report.rule_violations += 1;
+            continue;
+        }
+
+        for (i, id) in ids.iter().enumerate() {
+            report.total_scores[i] += game.score(id).unwrap() as i64;
+        }
+        match game.winner() {
+            Some(winner) => report.wins[ids.iter().position(|id| *id == winner.id).unwrap()] += 1,
+            None => report.draws += 1,
+        }
+    }
+
+    report
+}
+
+fn play_out(game: &mut Game, strategies: &[&dyn BotStrategy], ids: &[Uuid]) -> bool {
+    for _ in 0..MAX_MOVES {
+        if game.phase == GamePhase::GameEnded {
+            return true;
+        }
+
+        let player_id = game.players[game.current_turn].id;
+        let strategy = strategies[ids.iter().position(|id| *id == player_id).unwrap()];
+        let action = match BotView::new(game, &player_id).and_then(|view| strategy.decide(&view)) {
+            Some(action) => action,
+            None => return false,
+        };
+        let res = match action {
+            BotAction::Draw => game.draw(&player_id),
+            BotAction::TakeBin => game.take_bin(&player_id),
+            BotAction::Discard(card) => game.discard(&player_id, card).map(|_| ()),
+            BotAction::Close(card) => game.close(&player_id, card).map(|_| ()),
+        };
+        if res.is_err() || !cards_accounted_for(game) {
+            return false;
+        }
+    }
+    false
+}
+
+fn cards_accounted_for(game: &Game) -> bool {
+    let in_play: usize = game.players.iter().map(|player| player.hand.len() + player.bin.len()).sum();
+    // The final discard or close takes its card out of play.
+    let expected = if game.phase == GamePhase::GameEnded { 51 } else { 52 };
+    in_play + game.deck.len() == expected
+}
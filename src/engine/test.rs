@@ -1,12 +1,17 @@
 #[cfg(test)]
 #[allow(warnings)]
 mod tests {
-    use crate::engine::game::{Game, GamePhase, GameStatus, MINIMUM_CLOSE_SCORE};
+    use crate::engine::game::{Game, GameError, GamePhase, GameStatus, MINIMUM_CLOSE_SCORE};
     use uuid::Uuid;
     use crate::engine::card::{Card, Rank, Suit};
+    use crate::engine::bot::{best_discard, BotAction, BotStrategy, BotView, GreedyBot, LookaheadBot, RandomBot};
+    use crate::engine::simulation::simulate;
+    use crate::engine::rules::RuleSet;
+    use crate::engine::game_match::{GameMatch, MatchSettings};
+    use crate::engine::replay::{replay, GameAction, GameLog};
 
     fn test_create_game() {
-        let game = Game::new(vec![Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()]);
+        let game = Game::new(vec![Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()], RuleSet::default());
 
     }
 
@@ -19,6 +24,11 @@ mod tests {
 
         assert_eq!(card.to_string(), "H2");
         assert_eq!(card.points(),2);
+
+        // Non-ASCII input is refused, whatever its length in bytes.
+        for input in ["é", "éA", "Hé", "H²", "♥2"] {
+            assert!(Card::from_string(input).is_none(), "{} parsed", input);
+        }
     }
 
 
@@ -26,7 +36,7 @@ mod tests {
     fn test_game_flow() {
         let player1_id = Uuid::new_v4();
         let player2_id = Uuid::new_v4();
-        let mut game = Game::new(vec![player1_id.clone(), player2_id.clone()]);
+        let mut game = Game::new(vec![player1_id.clone(), player2_id.clone()], RuleSet::default());
         let mut i = 0;
         loop {
             let current_player = game.current_player();
@@ -63,7 +73,7 @@ mod tests {
     fn test_game_step() {
         let player1_id = Uuid::new_v4();
         let player2_id = Uuid::new_v4();
-        let mut game = Game::new(vec![player1_id.clone(), player2_id.clone()]);
+        let mut game = Game::new(vec![player1_id.clone(), player2_id.clone()], RuleSet::default());
 
         let current_player = game.current_player();
         game.draw(&current_player.id).unwrap();
@@ -84,7 +94,7 @@ mod tests {
     fn test_early_close() {
         let player1_id = Uuid::new_v4();
         let player2_id = Uuid::new_v4();
-        let mut game = Game::new(vec![player1_id.clone(), player2_id.clone()]);
+        let mut game = Game::new(vec![player1_id.clone(), player2_id.clone()], RuleSet::default());
         let mut i = 0;
         let collect_card_ranks = [Rank::Ace, Rank::King, Rank::Jack, Rank::Queen, Rank::Ten];
 
@@ -133,7 +143,7 @@ mod tests {
     fn test_close() {
         let player1_id = Uuid::new_v4();
         let player2_id = Uuid::new_v4();
-        let mut game = Game::new(vec![player1_id.clone(), player2_id.clone()]);
+        let mut game = Game::new(vec![player1_id.clone(), player2_id.clone()], RuleSet::default());
         let collect_card_ranks = [Rank::Ace, Rank::King, Rank::Jack, Rank::Queen, Rank::Ten];
         let calculate_n_points = |card: &Card| {
             let mut point = card.points() as i16;
@@ -179,4 +189,252 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_best_discard() {
+        let hand: Vec<Card> = ["HA", "HK", "HQ", "HJ", "S2"].iter().map(|c| Card::from_string(c).unwrap()).collect();
+        let (index, score) = best_discard(&hand, &RuleSet::default());
+
+        assert_eq!(hand[index].to_string(), "S2");
+        assert_eq!(score, 41);
+    }
+
+    #[test]
+    fn test_greedy_bot_game() {
+        let mut game = Game::new(vec![Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()], RuleSet::default());
+
+        while game.phase != GamePhase::GameEnded {
+            let player_id = game.current_player().id;
+            let action = GreedyBot.decide(&BotView::new(&game, &player_id).unwrap()).unwrap();
+            let res = match action {
+                BotAction::Draw => game.draw(&player_id),
+                BotAction::TakeBin => game.take_bin(&player_id),
+                BotAction::Discard(card) => game.discard(&player_id, card).map(|_| ()),
+                BotAction::Close(card) => game.close(&player_id, card).map(|_| ()),
+            };
+            assert!(res.is_ok(), "Bot made an illegal move: {:?}", res);
+        }
+    }
+
+    #[test]
+    fn test_bot_self_play() {
+        let report = simulate(&[&RandomBot, &GreedyBot, &LookaheadBot], &RuleSet::default(), 30, 1);
+
+        assert_eq!(report.games, 30);
+        assert_eq!(report.rule_violations, 0);
+        assert_eq!(report.wins.iter().sum::<usize>() + report.draws, 30);
+    }
+
+    /// Long running harness for tuning strategies, run with
+    /// `cargo test bot_tournament -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn test_bot_tournament() {
+        let names = ["random", "greedy", "lookahead"];
+        let report = simulate(&[&RandomBot, &GreedyBot, &LookaheadBot], &RuleSet::default(), 5000, 1);
+
+        println!("{} games, {} draws, {} rule violations", report.games, report.draws, report.rule_violations);
+        for (seat, name) in names.iter().enumerate() {
+            println!("{:>10}: win rate {:.3}, average score {:.2}", name, report.win_rate(seat), report.average_score(seat));
+        }
+        assert_eq!(report.rule_violations, 0);
+    }
+
+    #[test]
+    fn test_house_rules() {
+        let rules = RuleSet { hand_size: 5, ace_value: 1, face_card_value: 5, allow_close: false, ..RuleSet::default() };
+        assert!(rules.validate().is_ok());
+        assert_eq!(Card::from_string("HA").unwrap().points_with(&rules), 1);
+        assert_eq!(Card::from_string("HK").unwrap().points_with(&rules), 5);
+
+        let player1_id = Uuid::new_v4();
+        let mut game = Game::new(vec![player1_id, Uuid::new_v4()], rules.clone());
+        assert!(game.players.iter().all(|player| player.hand.len() == 5));
+
+        game.draw(&player1_id).unwrap();
+        let card = game.current_player().hand[0].clone();
+        assert!(game.close(&player1_id, card).is_err(), "Closing is disabled by the rules");
+
+        let report = simulate(&[&RandomBot, &GreedyBot], &rules, 30, 1);
+        assert_eq!(report.rule_violations, 0);
+
+        let too_many_cards = RuleSet { hand_size: 13, ..RuleSet::default() };
+        assert!(too_many_cards.validate().is_err());
+        let overflowing = RuleSet { hand_size: usize::MAX, max_players: 2, ..RuleSet::default() };
+        assert!(overflowing.validate().is_err());
+        let ace_too_high = RuleSet { ace_value: u16::MAX, ..RuleSet::default() };
+        assert!(ace_too_high.validate().is_err());
+        let endless_turns = RuleSet { turn_timeout_secs: Some(u64::MAX), ..RuleSet::default() };
+        assert!(endless_turns.validate().is_err());
+        let endless_countdown = RuleSet { start_countdown_secs: Some(u64::MAX), ..RuleSet::default() };
+        assert!(endless_countdown.validate().is_err());
+    }
+
+    #[test]
+    fn test_match_rounds() {
+        let ids = vec![Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+        let settings = MatchSettings { rounds: 3, eliminate_lowest: true, ..MatchSettings::default() };
+        let mut game_match = GameMatch::new(ids.clone(), settings);
+        let hands = [["HA", "HK", "HQ", "HJ"], ["HA", "HK", "S2", "S3"], ["H2", "S3", "D4", "C5"]];
+
+        let mut starters = vec![];
+        while !game_match.is_over() {
+            let mut game = game_match.start_round(RuleSet::default());
+            starters.push(game.players[0].id);
+            for player in game.players.iter_mut() {
+                let seat = ids.iter().position(|id| *id == player.id).unwrap();
+                player.hand = hands[seat].iter().map(|c| Card::from_string(c).unwrap()).collect();
+            }
+            game.phase = GamePhase::GameEnded;
+            game_match.record_round(&game);
+        }
+
+        // The lowest player is dropped after the first round, elimination stops at two players.
+        assert_eq!(game_match.rounds_played, 3);
+        assert_eq!(game_match.eliminated, vec![ids[2]]);
+        assert_eq!(starters, vec![ids[0], ids[1], ids[0]]);
+        assert_eq!(game_match.totals[&ids[0]], 123);
+        assert_eq!(game_match.totals[&ids[2]], -4);
+        assert_eq!(game_match.winner(), Some(ids[0]));
+
+        let target = MatchSettings { rounds: 10, target_score: Some(40), ..MatchSettings::default() };
+        let mut game_match = GameMatch::new(ids.clone(), target);
+        let mut game = game_match.start_round(RuleSet::default());
+        for player in game.players.iter_mut() {
+            let seat = ids.iter().position(|id| *id == player.id).unwrap();
+            player.hand = hands[seat].iter().map(|c| Card::from_string(c).unwrap()).collect();
+        }
+        game.phase = GamePhase::GameEnded;
+        game_match.record_round(&game);
+        assert!(game_match.is_over(), "The target score ends the match early");
+    }
+
+    #[test]
+    fn test_remove_player() {
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        let mut game = Game::new(ids.clone(), RuleSet::default());
+        game.current_turn = 1;
+
+        // Removing an earlier seat keeps the turn with the same player.
+        game.remove_player(&ids[0]).unwrap();
+        assert_eq!(game.players[game.current_turn].id, ids[1]);
+
+        // Removing the current player passes the turn on.
+        game.remove_player(&ids[1]).unwrap();
+        assert_eq!(game.players.len(), 1);
+        assert_eq!(game.players[game.current_turn].id, ids[2]);
+
+        // After the round ended, the current player is dropped without playing on.
+        let mut ended = Game::new(ids.clone(), RuleSet::default());
+        ended.phase = GamePhase::GameEnded;
+        ended.current_turn = 2;
+        let bins: Vec<usize> = ended.players.iter().map(|player| player.bin.len()).collect();
+        ended.remove_player(&ids[2]).unwrap();
+        assert_eq!(ended.phase, GamePhase::GameEnded);
+        assert_eq!(ended.players.len(), 2);
+        assert_eq!(ended.current_turn, 0);
+        assert_eq!(ended.players.iter().map(|player| player.bin.len()).collect::<Vec<_>>(), bins[..2]);
+    }
+
+    #[test]
+    fn test_replay() {
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        let mut game = Game::from_seed(ids.clone(), RuleSet::default(), 3);
+        let mut turns = 0;
+
+        while game.phase != GamePhase::GameEnded {
+            // One seat leaves halfway through to cover `remove_player`.
+            if turns == 10 {
+                game.remove_player(&ids[1]).unwrap();
+            }
+            let player_id = game.current_player().id;
+            match GreedyBot.decide(&BotView::new(&game, &player_id).unwrap()).unwrap() {
+                BotAction::Draw => game.draw(&player_id).unwrap(),
+                BotAction::TakeBin => game.take_bin(&player_id).unwrap(),
+                BotAction::Discard(card) => { game.discard(&player_id, card).unwrap(); },
+                BotAction::Close(card) => { game.close(&player_id, card).unwrap(); },
+            }
+            turns += 1;
+        }
+        assert!(game.log.actions.contains(&GameAction::Leave { player_id: ids[1] }));
+        assert_eq!(game.version, game.log.actions.len() as u64);
+
+        let json = serde_json::to_string(&game.log).unwrap();
+        let log: GameLog = serde_json::from_str(&json).unwrap();
+        let states = replay(&log).unwrap();
+        assert_eq!(states.len(), log.actions.len() + 1);
+        assert_eq!(states[0].deck.len(), 52 - 3 * 4);
+
+        let last = states.last().unwrap();
+        assert_eq!(last.phase, GamePhase::GameEnded);
+        assert_eq!(last.current_turn, game.current_turn);
+        assert_eq!(last.deck, game.deck);
+        assert_eq!(last.log, game.log);
+        for (replayed, played) in last.players.iter().zip(game.players.iter()) {
+            assert_eq!(replayed.id, played.id);
+            assert_eq!(replayed.hand, played.hand);
+            assert_eq!(replayed.bin, played.bin);
+        }
+
+        // A log whose recorded card differs from the deal no longer replays.
+        let mut tampered = log.clone();
+        tampered.actions[0] = match &tampered.actions[0] {
+            GameAction::Draw { player_id, .. } | GameAction::TakeBin { player_id, .. } => GameAction::Draw {
+                player_id: *player_id,
+                card: tampered.deck[0].clone(),
+            },
+            action => panic!("Unexpected first action {:?}", action),
+        };
+        assert!(replay(&tampered).is_err());
+    }
+
+    #[test]
+    fn test_seeded_deal() {
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        let game = Game::from_seed(ids.clone(), RuleSet::default(), 41);
+        let same = Game::from_seed(ids.clone(), RuleSet::default(), 41);
+        let other = Game::from_seed(ids.clone(), RuleSet::default(), 42);
+        assert_eq!(game.seed, Some(41));
+        assert_eq!(game.deck, same.deck);
+        assert_eq!(game.players[0].hand, same.players[0].hand);
+        assert_ne!(game.log.deck, other.log.deck);
+
+        // A random game records its seed, which reproduces the deal.
+        let random = Game::new(ids.clone(), RuleSet::default());
+        let again = Game::from_seed(ids.clone(), RuleSet::default(), random.seed.unwrap());
+        assert_eq!(random.log.deck, again.log.deck);
+
+        // Duplicate play: two tables of a seeded match get the same deal every round.
+        let settings = MatchSettings { rounds: 2, seed: Some(7), ..MatchSettings::default() };
+        let other_ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        let mut table_a = GameMatch::new(ids.clone(), settings.clone());
+        let mut table_b = GameMatch::new(other_ids, settings);
+        let first_a = table_a.start_round(RuleSet::default());
+        let first_b = table_b.start_round(RuleSet::default());
+        assert_eq!(first_a.log.deck, first_b.log.deck);
+
+        table_a.rounds_played += 1;
+        table_b.rounds_played += 1;
+        let second_a = table_a.start_round(RuleSet::default());
+        assert_eq!(second_a.log.deck, table_b.start_round(RuleSet::default()).log.deck);
+        assert_ne!(second_a.log.deck, first_a.log.deck);
+    }
+
+    #[test]
+    fn test_move_errors() {
+        let ids = vec![Uuid::new_v4(), Uuid::new_v4()];
+        let mut game = Game::from_seed(ids.clone(), RuleSet::default(), 5);
+        let hand = ["H2", "D3", "C4", "S5"].iter().map(|c| Card::from_string(c).unwrap()).collect();
+        game.players[0].hand = hand;
+
+        assert!(matches!(game.draw(&ids[1]), Err(GameError::NotYourTurn)));
+        assert!(matches!(game.take_bin(&ids[0]), Err(GameError::EmptyBin)));
+        assert!(matches!(game.discard(&ids[0], Card::from_string("H2").unwrap()), Err(GameError::InvalidMove)));
+
+        game.draw(&ids[0]).unwrap();
+        assert!(matches!(game.discard(&ids[0], Card::from_string("HK").unwrap()), Err(GameError::CardNotFound)));
+        assert!(matches!(game.close(&ids[0], Card::from_string("H2").unwrap()), Err(GameError::ScoreTooLow)));
+
+        game.rules.allow_close = false;
+        assert!(matches!(game.close(&ids[0], Card::from_string("H2").unwrap()), Err(GameError::CloseNotAllowed)));
+    }
 }
\ No newline at end of file
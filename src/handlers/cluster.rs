@@ -0,0 +1,190 @@
+use crate::handlers::error::GameError;
+use crate::handlers::game::{accept_connection, export_replay, serve_socket, spawn_send_task};
+use crate::state::cluster::{ClusterMessage, ClusterNode, ConnectionKind, RelayedFrame};
+use crate::state::state::GameManager;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+/// How long a node waits for the host of a game to accept a relayed socket or answer a replay request.
+const OPEN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the host keeps a relayed socket it accepted before the other node upgrades it.
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accept a join, rejoin or watch socket. It is served here when this node hosts the game,
+/// otherwise its frames are relayed to and from the host.
+pub async fn connect(ws: WebSocketUpgrade, state: Arc<RwLock<GameManager>>, kind: ConnectionKind, game_id: String, params: HashMap<String, String>) -> Result<Response, (StatusCode, String)> {
+    let cluster = {
+        let game_manager = state.read().await;
+        if game_manager.shutting_down {
+            return Err(GameError::ShuttingDown.into());
+        }
+        game_manager.services.cluster.clone()
+    };
+
+    let host = match cluster.host(&game_id) {
+        Some(host) if host != cluster.id => host,
+        _ => {
+            let connection = accept_connection(&state, kind, &game_id, params).await?;
+            return Ok(ws.on_upgrade(move |socket| serve_socket(socket, state, connection)));
+        }
+    };
+
+    let connection = Uuid::new_v4();
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<Message>();
+    cluster.relay(connection, tx);
+    let opened = cluster.await_opened(connection);
+    cluster.send(&host, &ClusterMessage::Open { connection, node_id: cluster.id.clone(), kind, game_id, params });
+
+    let error = match tokio::time::timeout(OPEN_TIMEOUT, opened).await {
+        Ok(Ok(error)) => error,
+        _ => Some((StatusCode::GATEWAY_TIMEOUT.as_u16(), "Game host did not answer.".to_string())),
+    };
+    if let Some((status, message)) = error {
+        cluster.forget(connection);
+        return Err((StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY), message));
+    }
+    let failed = (cluster.clone(), host.clone());
+    Ok(ws
+        .on_failed_upgrade(move |e| {
+            let (cluster, host) = failed;
+            eprintln!("Relayed connection {} failed to upgrade: {:?}", connection, e);
+            cluster.send(&host, &ClusterMessage::Closed { connection });
+            cluster.forget(connection);
+        })
+        .on_upgrade(move |socket| relay_socket(socket, cluster, host, connection, rx)))
+}
+
+/// Export the replay of a game, from the node hosting it. `query` holds the request's query
+/// string, the replay options along with the lobby's invite code and password.
+pub async fn replay_from_host(state: Arc<RwLock<GameManager>>, game_id: String, query: String) -> Result<Response, (StatusCode, String)> {
+    let cluster = state.read().await.services.cluster.clone();
+    let host = match cluster.host(&game_id) {
+        Some(host) if host != cluster.id => host,
+        _ => return Ok(Json(export_replay(&state, game_id, &query).await?).into_response()),
+    };
+
+    let request = Uuid::new_v4();
+    let replayed = cluster.await_replay(request);
+    cluster.send(&host, &ClusterMessage::Replay { request, node_id: cluster.id.clone(), game_id, query });
+    let result = tokio::time::timeout(OPEN_TIMEOUT, replayed).await;
+    cluster.forget(request);
+    match result {
+        Ok(Ok(Ok(replay))) => Ok(Json(replay).into_response()),
+        Ok(Ok(Err((status, message)))) => Err((StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY), message)),
+        _ => Err((StatusCode::GATEWAY_TIMEOUT, "Game host did not answer.".to_string())),
+    }
+}
+
+/// Pass the frames of a client socket to the node hosting its game, and the host's frames back.
+async fn relay_socket(socket: WebSocket, cluster: Arc<ClusterNode>, host: String, connection: Uuid, rx: UnboundedReceiver<Message>) {
+    let (sender, mut receiver) = socket.split();
+    let send_task = spawn_send_task(sender, rx);
+    cluster.send(&host, &ClusterMessage::Ready { connection });
+
+    while let Some(Ok(message)) = receiver.next().await {
+        if let Some(frame) = RelayedFrame::from_message(message) {
+            cluster.send(&host, &ClusterMessage::Frame { connection, frame });
+        }
+    }
+    cluster.send(&host, &ClusterMessage::Closed { connection });
+    cluster.forget(connection);
+    send_task.abort();
+}
+
+/// Serve a socket held by another node as if it were connected here, once that node upgraded it.
+async fn serve_relayed(state: Arc<RwLock<GameManager>>, cluster: Arc<ClusterNode>, node_id: String, connection: Uuid, kind: ConnectionKind, game_id: String, params: HashMap<String, String>) {
+    let game_connection = match accept_connection(&state, kind, &game_id, params).await {
+        Ok(game_connection) => game_connection,
+        Err((status, message)) => {
+            cluster.send(&node_id, &ClusterMessage::Opened { connection, error: Some((status.as_u16(), message)) });
+            return;
+        }
+    };
+
+    let (in_tx, mut in_rx) = tokio::sync::mpsc::unbounded_channel::<Message>();
+    cluster.relay(connection, in_tx);
+    let ready = cluster.await_ready(connection);
+    cluster.send(&node_id, &ClusterMessage::Opened { connection, error: None });
+    // The player only takes a seat once there is a client socket to serve.
+    if !matches!(tokio::time::timeout(READY_TIMEOUT, ready).await, Ok(Ok(()))) {
+        eprintln!("Relayed connection {} was never upgraded", connection);
+        cluster.forget(connection);
+        return;
+    }
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Message>();
+    let forward_cluster = cluster.clone();
+    let proxy = node_id.clone();
+    let forward_task = tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            if let Some(frame) = RelayedFrame::from_message(message) {
+                forward_cluster.send(&proxy, &ClusterMessage::Frame { connection, frame });
+            }
+        }
+    });
+    let receiver = futures_util::stream::poll_fn(move |cx| in_rx.poll_recv(cx).map(|message| message.map(Ok)));
+
+    game_connection.serve(state, tx, receiver.boxed()).await;
+    cluster.send(&node_id, &ClusterMessage::Closed { connection });
+    cluster.forget(connection);
+    forward_task.abort();
+}
+
+/// Handle what the other nodes send this one, for as long as the node runs.
+pub async fn run_cluster_node(state: Arc<RwLock<GameManager>>) {
+    let cluster = state.read().await.services.cluster.clone();
+    let (mut inbox, mut lobbies) = cluster.subscribe();
+    loop {
+        let message = tokio::select! {
+            Some(payload) = inbox.recv() => ClusterMessage::decode(&payload),
+            Some(payload) = lobbies.recv() => ClusterMessage::decode(&payload),
+            else => break,
+        };
+        match message {
+            Some(ClusterMessage::Open { connection, node_id, kind, game_id, params }) => {
+                tokio::spawn(serve_relayed(state.clone(), cluster.clone(), node_id, connection, kind, game_id, params));
+            }
+            Some(ClusterMessage::Opened { connection, error }) => cluster.opened(connection, error),
+            Some(ClusterMessage::Ready { connection }) => cluster.ready(connection),
+            Some(ClusterMessage::Frame { connection, frame }) => cluster.deliver(connection, frame.into_message()),
+            Some(ClusterMessage::Closed { connection }) => {
+                cluster.deliver(connection, Message::Close(None));
+                cluster.forget(connection);
+            }
+            Some(ClusterMessage::Lobby { node_id, game_id, summary }) if node_id != cluster.id => {
+                state.read().await.services.lobby_feed().publish(&game_id, summary);
+            }
+            Some(ClusterMessage::Hello { node_id }) if node_id != cluster.id => {
+                let game_manager = state.read().await;
+                let lobby_feed = game_manager.services.lobby_feed();
+                for game_id in game_manager.games.keys() {
+                    if let Some(summary) = lobby_feed.summary(game_id) {
+                        let summary = Some(summary.clone());
+                        cluster.send(&node_id, &ClusterMessage::Lobby { node_id: cluster.id.clone(), game_id: game_id.clone(), summary });
+                    }
+                }
+            }
+            Some(ClusterMessage::Replay { request, node_id, game_id, query }) => {
+                let (state, cluster) = (state.clone(), cluster.clone());
+                tokio::spawn(async move {
+                    let result = export_replay(&state, game_id, &query).await
+                        .and_then(|replay| serde_json::to_value(replay).map_err(|e| GameError::ReplayFailed(e.to_string())))
+                        .map_err(|e| (e.status().as_u16(), e.to_string()));
+                    cluster.send(&node_id, &ClusterMessage::Replayed { request, result });
+                });
+            }
+            Some(ClusterMessage::Replayed { request, result }) => cluster.replayed(request, result),
+            _ => {}
+        }
+    }
+}
@@ -12,19 +12,53 @@ pub enum GameError {
     NotEnoughPlayers,
     #[error("Game is full")]
     GameFull,
+    #[error("Name already taken")]
+    NameTaken,
+    #[error("Too many spectators")]
+    SpectatorsFull,
     #[error("Invalid operation: {0}")]
     InvalidOperation(String),
+    #[error("Wrong or missing password")]
+    WrongPassword,
+    #[error("Invalid invite code")]
+    InvalidInviteCode,
+    #[error("Admin token required")]
+    AdminRequired,
+    #[error("Lobby is locked")]
+    LobbyLocked,
+    #[error("Server is restarting")]
+    ShuttingDown,
+    #[error("Replay failed: {0}")]
+    ReplayFailed(String),
 }
 
-impl axum::response::IntoResponse for GameError {
-    fn into_response(self) -> axum::response::Response {
-        let err = match self {
+impl GameError {
+    pub fn status(&self) -> StatusCode {
+        match self {
             GameError::GameNotFound => StatusCode::NOT_FOUND,
             GameError::GameAlreadyStarted => StatusCode::BAD_REQUEST,
             GameError::GameFull => StatusCode::BAD_REQUEST,
+            GameError::NameTaken => StatusCode::BAD_REQUEST,
+            GameError::SpectatorsFull => StatusCode::BAD_REQUEST,
             GameError::InvalidOperation(_) => StatusCode::BAD_REQUEST,
+            GameError::WrongPassword => StatusCode::UNAUTHORIZED,
+            GameError::InvalidInviteCode => StatusCode::FORBIDDEN,
+            GameError::AdminRequired => StatusCode::FORBIDDEN,
+            GameError::LobbyLocked => StatusCode::FORBIDDEN,
+            GameError::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
-        };
-        (err, self.to_string()).into_response()
+        }
+    }
+}
+
+impl From<GameError> for (StatusCode, String) {
+    fn from(err: GameError) -> Self {
+        (err.status(), err.to_string())
+    }
+}
+
+impl axum::response::IntoResponse for GameError {
+    fn into_response(self) -> axum::response::Response {
+        <(StatusCode, String)>::from(self).into_response()
     }
 }
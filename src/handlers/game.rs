@@ -1,489 +1,1535 @@
+use crate::engine::bot::{BotAction, BotDifficulty, BotView};
 use crate::engine::card::Card;
-use crate::engine::game::{Game, GamePhase, MAX_PLAYER};
+use crate::engine::game::{Game, GamePhase};
+use crate::engine::game_match::{GameMatch, MatchSettings};
+use crate::engine::replay::{self, GameLog};
+use crate::engine::rules::RuleSet;
+use crate::handlers::cluster::{connect, replay_from_host};
 use crate::handlers::error::GameError;
-use crate::state::state::{GameManager, GameState, GameStateStatus};
-use axum::extract::Query;
-use axum::http::StatusCode;
+use crate::protocol::{
+    ChatLine, Encoding, EndGameData, EndGameScores, ErrorCode, GameData, GameEvent, GameEventType, GameRequest,
+    GameRequestAction, MatchScoreData, MatchScoreEntry, PlayerData, PlayerInfoData, QueueData, RematchData, ServerMessage, SessionData, SocketOptions, UpdateMode,
+};
+use crate::state::actor::{GameContext, GameHandle};
+use crate::state::chat::{ChatFilter, MAX_CHAT_LENGTH};
+use crate::state::cluster::ConnectionKind;
+use crate::state::matchmaking::QueuedPlayer;
+use crate::state::state::{GameManager, GameState, GameStateStatus, LobbyAccess, PlayerConnection, MAX_SPECTATORS};
+use crate::utils::{constant_time_eq, sign_session_token, unix_millis, verify_session_token};
+use axum::extract::{Query, RawQuery};
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderMap, StatusCode};
 use axum::{
-    extract::{ws::{Message, WebSocket, WebSocketUpgrade}, Path, State},
+    extract::{ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade}, Path, State},
     response::IntoResponse,
     Json,
 };
+use chrono::Utc;
+use futures_util::stream::{BoxStream, SplitSink};
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::cmp::PartialEq;
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::{oneshot, RwLock};
+use tokio::task::JoinHandle;
 use uuid::Uuid;
 
+/// What a client sends on its socket, read from a local socket or relayed from another node.
+pub type ClientStream = BoxStream<'static, Result<Message, axum::Error>>;
+
 #[derive(Debug, Serialize)]
 pub struct CreateGameResponse {
     game_id: String,
     num_of_players: usize,
+    rules: RuleSet,
+    /// Without the seed, which stays on the server so no player can work out the deals.
+    match_settings: MatchSettings,
+    password_protected: bool,
+    /// Needed by everyone joining a private game.
+    invite_code: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Clone, PartialEq)]
-#[serde(rename_all = "snake_case")]
-enum GameRequestAction {
-    StartGame,
-    Draw,
-    TakeBin,
-    Discard,
-    Close,
+/// Who may join a new game, given to `/create` along with the rules.
+#[derive(Debug, Deserialize)]
+pub struct LobbyParams {
+    password: Option<String>,
+    #[serde(default)]
+    private: bool,
 }
 
+#[derive(Debug, Serialize)]
+pub struct ReplayResponse {
+    game_id: String,
+    /// Which finished match of the game the rounds belong to, starting at 1.
+    match_number: usize,
+    /// Finished matches of the game, earlier ones were followed by a rematch.
+    matches: usize,
+    rounds: Vec<RoundReplay>,
+}
+
+#[derive(Debug, Serialize)]
+struct RoundReplay {
+    log: GameLog,
+    /// Every intermediate state rebuilt from the log, only with `?states=true`.
+    states: Option<Vec<Game>>,
+}
 
 #[derive(Debug, Deserialize)]
-struct GameRequest  {
-    action: GameRequestAction,
-    card: Option<String>,
-}
-#[derive(Debug, Serialize, Deserialize)]
-struct GameResponse {
-    status: String,
-    message_type: MessageType,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct EndGameScores {
-    name: String,
-    score: i16,
-    hand: Vec<String>,
-}
-#[derive(Debug, Serialize, Deserialize)]
-struct EndGameData {
-    winner_name: Option<String>,
-    players: Vec<EndGameScores>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct EndGameMessage {
-    status: String,
-    message_type: MessageType,
-    data: EndGameData
-}
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-enum MessageType {
-    PlayerJoin,
-    PlayerLeft,
-    Reply,
-    GameEvent,
-    EndGame,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct PlayerInfoData {
-    players: Vec<PlayerData>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct PlayerInfoMessage {
-    message_type: MessageType,
-    status: String,
-    data: PlayerInfoData,
-    message: Option<String>
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct GameMessage {
-    message_type: MessageType,
-    status: String,
-    data: Option<GameData>,
-    message: Option<String>
-}
-#[derive(Debug, Serialize, Deserialize)]
-struct GameData {
-    player_id: Uuid,
-    player_pos: u8,
-    num_of_players: u8,
-    card_left: u8,
-    current_turn: u8,
-    current_phase: GamePhase,
-    event: GameEvent,
-    players: Vec<PlayerData>,
-
-}
-#[derive(Debug, Serialize, Deserialize)]
-struct PlayerData {
-    name: String,
-    hand: Vec<String>,
-    bin: Vec<String>,
-}
-#[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "snake_case")]
-enum GameEventType {
-    GameStart,
-    Draw,
-    TakeBin,
-    Discard,
-    Close,
-
-}
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct GameEvent {
-    event_type: GameEventType,
-    from: Option<u8>,
-    to: Option<u8>,
-}
-
-
-pub async fn create_game(State(state): State<Arc<RwLock<GameManager>>>) -> Result<Json<CreateGameResponse>, GameError>{
-    let game = state.write().await.create_game();
+pub struct ReplayParams {
+    #[serde(default)]
+    states: bool,
+    /// Defaults to the last finished match.
+    match_number: Option<usize>,
+}
+
+
+pub async fn create_game(State(state): State<Arc<RwLock<GameManager>>>, headers: HeaderMap, Query(rules): Query<RuleSet>, Query(match_settings): Query<MatchSettings>, Query(lobby): Query<LobbyParams>) -> Result<Json<CreateGameResponse>, GameError>{
+    rules.validate().map_err(GameError::InvalidOperation)?;
+    match_settings.validate().map_err(GameError::InvalidOperation)?;
+    if lobby.password.as_ref().is_some_and(|password| password.is_empty()) {
+        return Err(GameError::InvalidOperation("password must not be empty".to_string()));
+    }
+    // Anyone who knows the seed can work out every hand, only an admin may pick it.
+    if match_settings.seed.is_some() && !is_admin(state.read().await.admin_token.as_deref(), &headers) {
+        return Err(GameError::AdminRequired);
+    }
+    let access = tokio::task::spawn_blocking(move || LobbyAccess::new(lobby.password.as_deref(), lobby.private)).await
+        .expect("Hashing the lobby password panicked");
+    let mut game_manager = state.write().await;
+    if game_manager.shutting_down {
+        return Err(GameError::ShuttingDown);
+    }
+    let game = game_manager.create_game(rules, match_settings, access);
     Ok(Json(CreateGameResponse{
         game_id: game.id.clone(),
         num_of_players: game.players.len(),
+        rules: game.rules,
+        match_settings: MatchSettings { seed: None, ..game.match_settings },
+        password_protected: game.access.password_hash.is_some(),
+        invite_code: game.access.invite_code,
     }))
 }
 
 
+/// Whether the request carries the configured admin token as `Authorization: Bearer <token>`.
+pub fn is_admin(admin_token: Option<&str>, headers: &HeaderMap) -> bool {
+    let bearer = headers.get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match (admin_token, bearer) {
+        (Some(admin_token), Some(bearer)) => constant_time_eq(admin_token, bearer),
+        _ => false,
+    }
+}
+
+/// Export the logs of every round of a finished match.
+pub async fn replay(State(state): State<Arc<RwLock<GameManager>>>, Path(game_id): Path<String>, RawQuery(query): RawQuery) -> impl IntoResponse {
+    replay_from_host(state, game_id, query.unwrap_or_default()).await
+}
+
+/// Export a finished match of a game hosted on this node. `query` holds the `ReplayParams` along
+/// with the lobby's invite code and password.
+pub async fn export_replay(state: &Arc<RwLock<GameManager>>, game_id: String, query: &str) -> Result<ReplayResponse, GameError> {
+    let params: ReplayParams = serde_urlencoded::from_str(query).map_err(|e| GameError::InvalidOperation(e.to_string()))?;
+    let access: HashMap<String, String> = serde_urlencoded::from_str(query).map_err(|e| GameError::InvalidOperation(e.to_string()))?;
+    let handle = state.read().await.game(&game_id).ok_or(GameError::GameNotFound)?;
+    check_lobby_access(&handle, &access).await?;
+    let mut matches = handle.call(|game_state, _| {
+        let mut matches = game_state.past_matches.clone();
+        if game_state.status == GameStateStatus::Finished {
+            matches.push(game_state.round_logs.clone());
+        }
+        matches
+    }).await.ok_or(GameError::GameNotFound)?;
+    if matches.is_empty() {
+        return Err(GameError::InvalidOperation("Game is not finished yet".to_string()));
+    }
+    let match_count = matches.len();
+    let match_number = params.match_number.unwrap_or(match_count);
+    if match_number == 0 || match_number > match_count {
+        return Err(GameError::InvalidOperation(format!("Match number must be between 1 and {}", match_count)));
+    }
+    let round_logs = matches.swap_remove(match_number - 1);
+
+    // Rebuilt outside the game's task, so a long replay does not hold up the game.
+    let rounds = round_logs.into_iter().map(|log| {
+        let states = if params.states {
+            let mut states = replay::replay(&log).map_err(|e| GameError::ReplayFailed(format!("{:?}", e)))?;
+            // Each state would repeat the log so far, the round's log is sent once next to them.
+            for state in states.iter_mut() {
+                state.log = GameLog::default();
+            }
+            Some(states)
+        } else {
+            None
+        };
+        Ok(RoundReplay { log, states })
+    }).collect::<Result<Vec<_>, GameError>>()?;
+
+    Ok(ReplayResponse { game_id, match_number, matches: match_count, rounds })
+}
+
+
 pub async fn game(ws: WebSocketUpgrade, Path(game_id): Path<String>, Query(params): Query<HashMap<String, String>>, State(state): State<Arc<RwLock<GameManager>>>) -> impl IntoResponse {
+    connect(ws, state, ConnectionKind::Join, game_id, params).await
+}
+
+pub async fn rejoin(ws: WebSocketUpgrade, Path(game_id): Path<String>, Query(params): Query<HashMap<String, String>>, State(state): State<Arc<RwLock<GameManager>>>) -> impl IntoResponse {
+    connect(ws, state, ConnectionKind::Rejoin, game_id, params).await
+}
+
+pub async fn watch(ws: WebSocketUpgrade, Path(game_id): Path<String>, Query(params): Query<HashMap<String, String>>, State(state): State<Arc<RwLock<GameManager>>>) -> impl IntoResponse {
+    connect(ws, state, ConnectionKind::Watch, game_id, params).await
+}
+
+/// A socket accepted by the node hosting its game, served once the upgrade completes.
+pub enum GameConnection {
+    Player { handle: GameHandle, player_id: Uuid, player_name: String, options: SocketOptions },
+    Rejoin { handle: GameHandle, player_id: Uuid, options: SocketOptions },
+    Spectator { handle: GameHandle, spectator_id: Uuid, spectator_name: String, encoding: Encoding },
+}
+
+impl GameConnection {
+    /// Serve the socket until it closes, whether it is connected here or relayed from another node.
+    pub async fn serve(self, state: Arc<RwLock<GameManager>>, tx: UnboundedSender<Message>, receiver: ClientStream) {
+        match self {
+            GameConnection::Player { handle, player_id, player_name, options } => {
+                handle_game_connection(tx, receiver, state, handle, player_id, player_name, options).await
+            }
+            GameConnection::Rejoin { handle, player_id, options } => {
+                handle_rejoin_connection(tx, receiver, state, handle, player_id, options).await
+            }
+            GameConnection::Spectator { handle, spectator_id, spectator_name, encoding } => {
+                handle_watch_connection(tx, receiver, handle, spectator_id, spectator_name, encoding).await
+            }
+        }
+    }
+}
+
+/// Check a join, rejoin or watch request against a game hosted on this node.
+pub async fn accept_connection(state: &Arc<RwLock<GameManager>>, kind: ConnectionKind, game_id: &str, params: HashMap<String, String>) -> Result<GameConnection, (StatusCode, String)> {
+    let handle = {
+        let game_manager = state.read().await;
+
+        if game_manager.shutting_down {
+            return Err(GameError::ShuttingDown.into());
+        }
+
+        match game_manager.game(game_id) {
+            Some(handle) => handle,
+            None => return Err((StatusCode::BAD_REQUEST, "Game not found.".to_string())),
+        }
+    };
+    match kind {
+        ConnectionKind::Join => accept_join(handle, params).await,
+        ConnectionKind::Rejoin => accept_rejoin(state, handle, params).await,
+        ConnectionKind::Watch => accept_watch(handle, params).await,
+    }
+}
+
+async fn accept_join(handle: GameHandle, params: HashMap<String, String>) -> Result<GameConnection, (StatusCode, String)> {
+    let options = SocketOptions::from_params(&params).map_err(|message| (StatusCode::BAD_REQUEST, message.to_string()))?;
+    check_lobby_access(&handle, &params).await?;
+    let player_name = match handle.call(move |game_state, _| check_join(game_state, params.get("player_name"))).await {
+        Some(result) => result?,
+        None => return Err((StatusCode::BAD_REQUEST, "Game not found.".to_string())),
+    };
+    Ok(GameConnection::Player { handle, player_id: Uuid::new_v4(), player_name, options })
+}
+
+/// Check that a new player may take a seat, once they passed `check_lobby_access`, and pick their name.
+pub fn check_join(game_state: &GameState, player_name: Option<&String>) -> Result<String, GameError> {
+    if game_state.players.len() >= game_state.rules.max_players {
+        return Err(GameError::GameFull);
+    }
+
+    if game_state.status != GameStateStatus::Lobby {
+        return Err(GameError::GameAlreadyStarted);
+    }
+
+    if game_state.locked {
+        return Err(GameError::LobbyLocked);
+    }
+
+    let player_name = match player_name {
+        Some(name) => name.to_string(),
+        None => format!("Player {}", game_state.players.len()),
+    };
+
+    if game_state.players.values().any(|p| p.name == player_name) {
+        return Err(GameError::NameTaken);
+    }
+    Ok(player_name)
+}
+
+async fn accept_rejoin(state: &Arc<RwLock<GameManager>>, handle: GameHandle, params: HashMap<String, String>) -> Result<GameConnection, (StatusCode, String)> {
+    let options = SocketOptions::from_params(&params).map_err(|message| (StatusCode::BAD_REQUEST, message.to_string()))?;
+    let session_secret = state.read().await.session_secret.clone();
+    let player_id = match params.get("token").and_then(|token| verify_session_token(&session_secret, &handle.id, token)) {
+        Some(player_id) => player_id,
+        None => return Err((StatusCode::UNAUTHORIZED, "Invalid session token.".to_string())),
+    };
+
+    let seated = handle.call(move |game_state, _| game_state.players.contains_key(&player_id)).await;
+    if seated != Some(true) {
+        return Err((StatusCode::BAD_REQUEST, "Seat is no longer available.".to_string()));
+    }
+    Ok(GameConnection::Rejoin { handle, player_id, options })
+}
+
+async fn accept_watch(handle: GameHandle, params: HashMap<String, String>) -> Result<GameConnection, (StatusCode, String)> {
+    let encoding = match Encoding::from_param(params.get("encoding")) {
+        Some(encoding) => encoding,
+        None => return Err((StatusCode::BAD_REQUEST, "Unsupported encoding.".to_string())),
+    };
+    let spectator_name = params.get("spectator_name").cloned().unwrap_or_else(|| "Spectator".to_string());
+    check_lobby_access(&handle, &params).await?;
+    handle.call(|game_state, _| check_watch(game_state)).await.unwrap_or(Err(GameError::GameNotFound))?;
+    Ok(GameConnection::Spectator { handle, spectator_id: Uuid::new_v4(), spectator_name, encoding })
+}
+
+/// Check that a spectator who passed `check_lobby_access` may watch the game.
+pub fn check_watch(game_state: &GameState) -> Result<(), GameError> {
+    if game_state.spectators.len() >= MAX_SPECTATORS {
+        return Err(GameError::SpectatorsFull);
+    }
+    Ok(())
+}
+
+/// Queue for a quick match at a table of `players` seats. The socket becomes the player's game
+/// socket once the table is full or the matchmaking timeout fills it with bots.
+pub async fn quick_match(ws: WebSocketUpgrade, Query(params): Query<HashMap<String, String>>, State(state): State<Arc<RwLock<GameManager>>>) -> impl IntoResponse {
+
+    let options = match SocketOptions::from_params(&params) {
+        Ok(options) => options,
+        Err(message) => return Err((StatusCode::BAD_REQUEST, message).into_response()),
+    };
+    let table_size = match params.get("players").map(|players| players.parse::<usize>()) {
+        Some(Ok(players)) => players,
+        Some(Err(_)) => return Err((StatusCode::BAD_REQUEST, "Invalid table size.").into_response()),
+        None => RuleSet::default().max_players,
+    };
+    let rules = RuleSet { max_players: table_size, ..Default::default() };
+    if let Err(message) = rules.validate() {
+        return Err((StatusCode::BAD_REQUEST, message).into_response());
+    }
+    if state.read().await.shutting_down {
+        return Err(GameError::ShuttingDown.into_response());
+    }
+    let player_name = params.get("player_name").cloned().unwrap_or_else(|| "Player".to_string());
+    Ok(ws.on_upgrade(move |socket| handle_quick_match_connection(socket, state, player_name, table_size, options)))
+}
+
+/// Check the `invite` and `password` query parameters against the lobby's access settings. The
+/// password is verified off the game's task, as it is slow on purpose.
+async fn check_lobby_access(handle: &GameHandle, params: &HashMap<String, String>) -> Result<(), GameError> {
+    let access = handle.call(|game_state, _| game_state.access.clone()).await.ok_or(GameError::GameNotFound)?;
+    if !access.check_invite(params.get("invite")) {
+        return Err(GameError::InvalidInviteCode);
+    }
+    let password = params.get("password").cloned();
+    let password_ok = tokio::task::spawn_blocking(move || access.check_password(password.as_ref())).await
+        .expect("Verifying the lobby password panicked");
+    if !password_ok {
+        return Err(GameError::WrongPassword);
+    }
+    Ok(())
+}
+
+async fn handle_game_connection(tx: UnboundedSender<Message>, receiver: ClientStream, state: Arc<RwLock<GameManager>>, handle: GameHandle, player_id: Uuid, player_name: String, options: SocketOptions) {
+
+    let connection_id = Uuid::new_v4();
+    let token = sign_session_token(&state.read().await.session_secret, &handle.id, &player_id);
+
+    let sender = tx.clone();
+    let seated = handle.call(move |game_state, ctx| {
+        // Others may have joined, or the lobby started or been locked, since the handshake.
+        if let Err(e) = check_join(game_state, Some(&player_name)) {
+            let _ = sender.send(Message::Close(Some(CloseFrame { code: close_code::POLICY, reason: e.to_string().into() })));
+            return false;
+        }
+        let session = ServerMessage::Session {
+            data: SessionData { player_id, token, protocol_version: options.protocol_version },
+        };
+        if let Err(e) = sender.send(session.encode(options.encoding)) {
+            eprintln!("Error sending message: {:?}", e);
+        }
+
+        game_state.players.insert(player_id, PlayerConnection {
+            name: player_name.clone(),
+            sender: Some(sender),
+            connection_id,
+            bot: None,
+            encoding: options.encoding,
+            updates: options.updates,
+            views: Default::default(),
+        });
+        broadcast_player_join(game_state, format!("{} joined game", player_name));
+        hand_over_host(game_state);
+        schedule_auto_start(ctx, game_state);
+        ctx.save(game_state);
+        true
+    }).await;
+    // The game may have been removed between the handshake and the upgrade.
+    if seated != Some(true) {
+        return;
+    }
+
+    handle_socket_messages(receiver, &tx, options.encoding, &handle, player_id).await;
+    handle_disconnect(&state, &handle, player_id, connection_id).await;
+}
+
+async fn handle_quick_match_connection(socket: WebSocket, state: Arc<RwLock<GameManager>>, player_name: String, table_size: usize, options: SocketOptions) {
 
+    let (sender, mut receiver) = socket.split();
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<Message>();
+    let send_task = spawn_send_task(sender, rx);
     let player_id = Uuid::new_v4();
-    let player_name: String;
+    let connection_id = Uuid::new_v4();
+    let (matched_tx, mut matched_rx) = oneshot::channel::<String>();
+
     {
-        let game_manager = state.write().await;
+        let mut write_state = state.write().await;
+        let queued = QueuedPlayer { player_id, name: player_name, connection_id, sender: tx.clone(), options, matched: matched_tx };
+        match write_state.matchmaker.join(table_size, queued) {
+            Some(players) => start_quick_match(&mut write_state, table_size, players),
+            None => {
+                broadcast_queue_status(&write_state, table_size);
+                schedule_queue_timeout(&state, write_state.matchmaking_timeout, table_size, player_id);
+            }
+        }
+    }
 
-        if !game_manager.games.contains_key(&game_id) {
-            return Err((StatusCode::BAD_REQUEST, "Game not found.").into_response());
+    // Messages sent while waiting are ignored, they are read to notice the socket closing.
+    let matched = tokio::select! {
+        game_id = &mut matched_rx => game_id.ok(),
+        _ = async { while let Some(Ok(_)) = receiver.next().await {} } => None,
+    };
+    let game_id = match matched {
+        Some(game_id) => Some(game_id),
+        None => {
+            let mut write_state = state.write().await;
+            match write_state.matchmaker.leave(&player_id) {
+                Some(table_size) => {
+                    broadcast_queue_status(&write_state, table_size);
+                    None
+                }
+                // Seated while the socket was closing, the seat is released like on any disconnect.
+                None => matched_rx.try_recv().ok(),
+            }
         }
+    };
+
+    let handle = match game_id {
+        Some(game_id) => state.read().await.game(&game_id),
+        None => None,
+    };
+    if let Some(handle) = handle {
+        handle_socket_messages(receiver.boxed(), &tx, options.encoding, &handle, player_id).await;
+        handle_disconnect(&state, &handle, player_id, connection_id).await;
+    }
+    send_task.abort();
+}
+
+fn broadcast_queue_status(game_manager: &GameManager, table_size: usize) {
+    let waiting = game_manager.matchmaker.waiting(table_size);
+    let msg = ServerMessage::QueueStatus {
+        data: QueueData { table_size, waiting: waiting.len(), timeout_secs: game_manager.matchmaking_timeout.as_secs() },
+    };
+    for queued in waiting {
+        queued.send_message(&msg);
+    }
+}
+
+/// Seat whoever is still waiting with the player once the matchmaking timeout runs out.
+fn schedule_queue_timeout(state: &Arc<RwLock<GameManager>>, timeout: Duration, table_size: usize, player_id: Uuid) {
+    let state = state.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(timeout).await;
+        let mut write_state = state.write().await;
+        if let Some(players) = write_state.matchmaker.take_waiting(table_size, &player_id) {
+            start_quick_match(&mut write_state, table_size, players);
+        }
+    });
+}
+
+/// Create a game for matched players, fill the free seats with bots and start it.
+fn start_quick_match(game_manager: &mut GameManager, table_size: usize, players: Vec<QueuedPlayer>) {
+    let rules = RuleSet { max_players: table_size, ..Default::default() };
+    // Private, so the table never shows up in the lobby list before it starts.
+    let game_id = game_manager.create_game(rules, MatchSettings::default(), LobbyAccess::new(None, true)).id;
+    let secret = game_manager.session_secret.clone();
+    game_manager.games[&game_id].send(move |game_state, ctx| seat_quick_match(game_state, ctx, &secret, table_size, players));
+}
 
-        if game_manager.games[&game_id].players.len() >= MAX_PLAYER {
-            return Err((StatusCode::BAD_REQUEST, "Max player has been reached.").into_response());
+/// Seat the matched players who are still waiting, fill the other seats with bots and start the
+/// game. The game is removed when every player gave up.
+pub fn seat_quick_match(game_state: &mut GameState, ctx: &GameContext, secret: &str, table_size: usize, players: Vec<QueuedPlayer>) {
+    let game_id = game_state.id.clone();
+    for queued in players {
+        // The player's socket may have closed while the table was being created.
+        if queued.matched.is_closed() {
+            eprintln!("Quick-match player {} left before being seated", queued.player_id);
+            continue;
+        }
+        let name = (1..).map(|i| if i == 1 { queued.name.clone() } else { format!("{} {}", queued.name, i) })
+            .find(|name| !game_state.players.values().any(|p| &p.name == name))
+            .unwrap();
+        let token = sign_session_token(secret, &game_id, &queued.player_id);
+        queued.send_message(&ServerMessage::Session {
+            data: SessionData { player_id: queued.player_id, token, protocol_version: queued.options.protocol_version },
+        });
+        if queued.matched.send(game_id.clone()).is_err() {
+            eprintln!("Quick-match player {} left before being seated", queued.player_id);
+            continue;
         }
+        game_state.players.insert(queued.player_id, PlayerConnection {
+            name: name.clone(),
+            sender: Some(queued.sender),
+            connection_id: queued.connection_id,
+            bot: None,
+            encoding: queued.options.encoding,
+            updates: queued.options.updates,
+            views: Default::default(),
+        });
+        broadcast_player_join(game_state, format!("{} joined game", name));
+    }
+    if game_state.players.is_empty() {
+        ctx.remove(game_state);
+        return;
+    }
+    while game_state.players.len() < table_size {
+        seat_bot(game_state, BotDifficulty::default());
+    }
 
-        if game_manager.games[&game_id].status != GameStateStatus::Lobby {
-            return Err((StatusCode::BAD_REQUEST, "Game already started.").into_response());
+    hand_over_host(game_state);
+    // Everyone asked to play by queueing, nobody is waited on.
+    game_state.ready = game_state.players.keys().copied().collect();
+    let host = game_state.host.or_else(|| game_state.players.keys().next().copied()).unwrap();
+    if let Err(code) = apply_game_request(game_state, host, &GameRequest::new(GameRequestAction::StartGame, None)) {
+        eprintln!("Error starting quick match {}: {:?}", game_id, code);
+    }
+    play_bot_turns(ctx, game_state);
+    schedule_turn_timeout(ctx, game_state);
+    ctx.save(game_state);
+}
+
+async fn handle_rejoin_connection(tx: UnboundedSender<Message>, receiver: ClientStream, state: Arc<RwLock<GameManager>>, handle: GameHandle, player_id: Uuid, options: SocketOptions) {
+
+    let connection_id = Uuid::new_v4();
+    let token = sign_session_token(&state.read().await.session_secret, &handle.id, &player_id);
+
+    let sender = tx.clone();
+    let seated = handle.call(move |game_state, ctx| {
+        // The seat may have been vacated between the handshake and the upgrade.
+        if !game_state.players.contains_key(&player_id) {
+            return false;
         }
 
-        player_name = {
-            match params.get("player_name") {
-                Some(name) => name.to_string(),
-                None => {format!("Player {}",game_manager.games[&game_id].players.len() )}
+        let player = game_state.players.get_mut(&player_id).unwrap();
+        player.sender = Some(sender);
+        player.connection_id = connection_id;
+        player.encoding = options.encoding;
+        player.updates = options.updates;
+        player.views = Default::default();
+        player.send_message(&ServerMessage::Session { data: SessionData { player_id, token, protocol_version: options.protocol_version } });
+        let rejoin_message = format!("{} rejoined game", player.name);
+        broadcast_player_join(game_state, rejoin_message);
+        hand_over_host(game_state);
+
+        if let Some(game) = &game_state.game {
+            let game_event = GameEvent {
+                event_type: GameEventType::Reconnect,
+                from: game.player_pos(&player_id).map(|pos| pos as u8),
+                to: None,
+            };
+            let view = build_game_data(&player_id, game, game_state, game_event);
+            send_game_view(game_state.players.get_mut(&player_id).unwrap(), view, true);
+
+            if game.phase == GamePhase::GameEnded {
+                let msg = build_end_game_message(game_state);
+                game_state.players[&player_id].send_message(&msg);
             }
-        };
+        }
 
-        if game_manager.games[&game_id].players.values().any(|p| {
-            p.0 == player_name
-        }) {
-            return Err((StatusCode::BAD_REQUEST, "Name already taken.").into_response());
+        if let Some(msg) = build_match_score_message(game_state) {
+            game_state.players[&player_id].send_message(&msg);
         }
 
-        if game_manager.games.get(&game_id).is_none() {
-            eprintln!("Game with ID {} not found", game_id);
+        let history = game_state.chat.history();
+        if !history.is_empty() {
+            game_state.players[&player_id].send_message(&ServerMessage::ChatHistory { data: history });
         }
+        ctx.save(game_state);
+        true
+    }).await;
+    if seated != Some(true) {
+        return;
     }
-    Ok(ws.on_upgrade(move |socket| handle_game_connection(socket, state, player_id,player_name, game_id)))
+
+    handle_socket_messages(receiver, &tx, options.encoding, &handle, player_id).await;
+    handle_disconnect(&state, &handle, player_id, connection_id).await;
 }
 
+async fn handle_watch_connection(tx: UnboundedSender<Message>, mut receiver: ClientStream, handle: GameHandle, spectator_id: Uuid, spectator_name: String, encoding: Encoding) {
+
+    let watching = handle.call(move |game_state, _| {
+        // Others may have started watching since the handshake.
+        if game_state.spectators.len() >= MAX_SPECTATORS {
+            return false;
+        }
+        game_state.spectators.insert(spectator_id, PlayerConnection {
+            name: spectator_name.clone(),
+            sender: Some(tx),
+            connection_id: spectator_id,
+            bot: None,
+            encoding,
+            updates: UpdateMode::Full,
+            views: Default::default(),
+        });
+        broadcast_player_join(game_state, format!("{} is watching", spectator_name));
+
+        let spectator = &game_state.spectators[&spectator_id];
+        if let Some(game) = &game_state.game {
+            let game_event = GameEvent {
+                event_type: GameEventType::Watch,
+                from: None,
+                to: None,
+            };
+            let data = build_game_data(&spectator_id, game, game_state, game_event);
+            spectator.send_message(&ServerMessage::GameEvent { data });
+
+            if game.phase == GamePhase::GameEnded {
+                let msg = build_end_game_message(game_state);
+                spectator.send_message(&msg);
+            }
+        }
+
+        if let Some(msg) = build_match_score_message(game_state) {
+            spectator.send_message(&msg);
+        }
+        true
+    }).await;
+    if watching != Some(true) {
+        return;
+    }
 
-async fn handle_game_connection(socket: WebSocket, state: Arc<RwLock<GameManager>>, player_id: Uuid, player_name: String, game_id: String) {
+    // Spectators cannot act, their messages are read only to notice the socket closing.
+    while let Some(Ok(_)) = receiver.next().await {}
 
+    handle.send(move |game_state, _| {
+        if let Some(spectator) = game_state.spectators.remove(&spectator_id) {
+            broadcast_player_left(game_state, format!("{} stopped watching", spectator.name));
+        }
+    });
+}
 
-    let (mut sender, mut receiver) = socket.split();
-    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Message>();
+/// Serve a socket connected to this node. Its frames go out through a channel, like those of sockets relayed from other nodes.
+pub async fn serve_socket(socket: WebSocket, state: Arc<RwLock<GameManager>>, connection: GameConnection) {
+    let (sender, receiver) = socket.split();
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<Message>();
+    let send_task = spawn_send_task(sender, rx);
+    connection.serve(state, tx, receiver.boxed()).await;
+    send_task.abort();
+}
 
-    let send_task = tokio::spawn(async move {
+pub fn spawn_send_task(mut sender: SplitSink<WebSocket, Message>, mut rx: UnboundedReceiver<Message>) -> JoinHandle<()> {
+    tokio::spawn(async move {
        while let Some(message) = rx.recv().await {
            if sender.send(message).await.is_err() {
                continue;
            }
        }
+    })
+}
+
+/// Pass the requests of a seated player's socket to their game. Once the game's task is gone the
+/// client is told so and the socket is closed.
+pub async fn handle_socket_messages(mut receiver: ClientStream, tx: &UnboundedSender<Message>, encoding: Encoding, handle: &GameHandle, player_id: Uuid) {
+    while let Some(Ok(message)) = receiver.next().await {
+
+        // Requests come as JSON text or MessagePack binary, whatever the encoding of the replies.
+        let value = match message {
+            Message::Text(msg) => serde_json::from_str::<serde_json::Value>(&msg).ok(),
+            Message::Binary(bytes) => rmp_serde::from_slice::<serde_json::Value>(&bytes).ok(),
+            _ => continue,
+        };
+        let sent = match value.clone().and_then(|value| serde_json::from_value::<GameRequest>(value).ok()) {
+            Some(data) => handle.send(move |game_state, ctx| handle_game_data(game_state, ctx, player_id, data)),
+            None => {
+                // Still echo the request id when the rest of the request is invalid.
+                let request_id = value.and_then(|value| value.get("request_id")?.as_str().map(str::to_string));
+                handle.send(move |game_state, _| {
+                    send_reply(game_state, &player_id, request_id, Some(ErrorCode::MalformedRequest));
+                })
+            }
+        };
+        if !sent {
+            let msg = ServerMessage::GameClosed { message: "Game is no longer running".to_string() };
+            let _ = tx.send(msg.encode(encoding));
+            let _ = tx.send(Message::Close(Some(CloseFrame { code: close_code::ERROR, reason: "Game closed".into() })));
+            break;
+        }
+    }
+}
+
+/// Detach the socket from the player's seat and vacate it once the reconnect grace period runs out.
+async fn handle_disconnect(state: &Arc<RwLock<GameManager>>, handle: &GameHandle, player_id: Uuid, connection_id: Uuid) {
+    let (shutting_down, grace) = {
+        let game_manager = state.read().await;
+        (game_manager.shutting_down, game_manager.reconnect_grace)
+    };
+    // Seats stay taken so players can rejoin once the server is back.
+    if shutting_down {
+        return;
+    }
+
+    handle.send(move |game_state, ctx| {
+        let player = match game_state.players.get_mut(&player_id) {
+            // A newer socket took over this seat, nothing to release.
+            Some(player) if player.connection_id == connection_id => player,
+            _ => return,
+        };
+
+        if grace.is_zero() {
+            if let Err(e) = vacate_seat(game_state, &player_id, "left game") {
+                eprintln!("Unable to free the seat of player {}: {:?}", player_id, e);
+            }
+            play_bot_turns(ctx, game_state);
+            schedule_auto_start(ctx, game_state);
+            schedule_turn_timeout(ctx, game_state);
+            ctx.save(game_state);
+            return;
+        }
+
+        player.sender = None;
+        let disconnect_message = format!("{} disconnected", player.name);
+        broadcast_player_left(game_state, disconnect_message);
+        hand_over_host(game_state);
+        schedule_seat_release(ctx, player_id, connection_id, grace);
     });
+}
 
-    {
+/// Start the grace period for every seat of games restored from storage, since none of them has a socket yet.
+pub async fn release_restored_seats(state: Arc<RwLock<GameManager>>) {
+    let game_manager = state.read().await;
+    let grace = game_manager.reconnect_grace;
+    for handle in game_manager.games.values() {
+        handle.send(move |game_state, ctx| {
+            for (player_id, player) in game_state.players.iter().filter(|(_, player)| player.bot.is_none()) {
+                schedule_seat_release(ctx, *player_id, player.connection_id, grace);
+            }
+        });
+    }
+}
+
+/// Periodically remove expired games, telling whoever is still connected why before closing their socket.
+pub async fn collect_expired_games(state: Arc<RwLock<GameManager>>) {
+    let expiry = state.read().await.expiry;
+    let mut interval = tokio::time::interval(expiry.interval);
+    loop {
+        interval.tick().await;
+        let now = Utc::now();
+        let handles: Vec<GameHandle> = state.read().await.games.values().cloned().collect();
+        for handle in handles {
+            let kept = handle.call(move |game_state, ctx| {
+                let reason = match game_state.expired(now, &expiry) {
+                    Some(reason) => reason,
+                    None => return true,
+                };
+                eprintln!("Removing game {}: {}", game_state.id, reason);
+                broadcast_message(&ServerMessage::GameClosed { message: reason.to_string() }, game_state);
+                let close = Message::Close(Some(CloseFrame { code: close_code::NORMAL, reason: reason.into() }));
+                for connection in game_state.players.values().chain(game_state.spectators.values()) {
+                    connection.send(close.clone());
+                }
+                ctx.remove(game_state);
+                false
+            }).await;
+            // Also forget games whose task stopped after a panic.
+            if kept != Some(true) {
+                state.write().await.games.remove(&handle.id);
+            }
+        }
+    }
+}
+
+/// How long clients get to close their sockets once told the server is restarting.
+const SHUTDOWN_DRAIN: Duration = Duration::from_secs(5);
+
+/// Stop taking new games and players, persist and stop every game and ask every client to come back
+/// after the restart. Returns once the sockets closed, or after `SHUTDOWN_DRAIN` at the latest.
+pub async fn shut_down(state: Arc<RwLock<GameManager>>) {
+    let close = Message::Close(Some(CloseFrame { code: close_code::RESTART, reason: "Server restarting".into() }));
+    let mut senders = vec![];
+    let (restarting, handles) = {
         let mut write_state = state.write().await;
-        let game_state = write_state.games.get_mut(&game_id).unwrap();
-        game_state.players.insert(player_id, (player_name.clone(), tx));
-        let join_message = format!("{} joined game", player_name);
-        let join_json = PlayerInfoMessage {
-            status: "success".to_string(),
-            message_type: MessageType::PlayerJoin,
-            data: PlayerInfoData {
-                players: game_state.players.iter().map(|(_, v)| {
-                    PlayerData {
-                        name: v.0.clone(),
-                        hand: vec![],
-                        bin: vec![],
-                    }
-                }).collect(),
-            },
-            message: Some(join_message),
+        write_state.shutting_down = true;
+        // Nobody is told to rejoin games kept in memory only, or with tokens the restart invalidates.
+        let resumable = write_state.resumable();
+        let restarting = move || ServerMessage::ServerRestarting {
+            message: if resumable { "Server restarting, rejoin in a moment" } else { "Server stopping, this game cannot be resumed" }.to_string(),
+            resumable,
         };
-        broadcast_message(serde_json::to_string(&join_json).unwrap().to_string(), game_state).await;
+        let msg = restarting();
 
+        for queued in write_state.matchmaker.queued() {
+            queued.send_message(&msg);
+            let _ = queued.sender.send(close.clone());
+            senders.push(queued.sender.clone());
+        }
+        let encoded = msg.encode(Encoding::Json);
+        for watcher in write_state.services.lobby_feed().watchers.values() {
+            let _ = watcher.send(encoded.clone());
+            let _ = watcher.send(close.clone());
+            senders.push(watcher.clone());
+        }
+        (restarting, write_state.games.values().cloned().collect::<Vec<GameHandle>>())
+    };
 
+    for handle in handles {
+        let (msg, close) = (restarting(), close.clone());
+        let game_senders = handle.call(move |game_state, ctx| {
+            ctx.stop(game_state);
+            broadcast_message(&msg, game_state);
+            game_state.players.values().chain(game_state.spectators.values()).filter_map(|connection| {
+                connection.send(close.clone());
+                connection.sender.clone()
+            }).collect::<Vec<_>>()
+        }).await;
+        senders.extend(game_senders.unwrap_or_default());
+    }
+    state.read().await.services.storage().flush().await;
 
+    // A sender closes once its connection handler finished with the socket.
+    let drained = tokio::time::timeout(SHUTDOWN_DRAIN, async {
+        for sender in &senders {
+            sender.closed().await;
+        }
+    }).await;
+    if drained.is_err() {
+        eprintln!("Shutting down with sockets still open");
     }
+}
 
-    while let Some(Ok(message)) = receiver.next().await {
+/// Restart the turn timers and the bots of games restored from storage.
+pub async fn resume_turn_timers(state: Arc<RwLock<GameManager>>) {
+    for handle in state.read().await.games.values() {
+        handle.send(|game_state, ctx| {
+            // The server may have stopped while a bot was deciding.
+            play_bot_turns(ctx, game_state);
+            schedule_turn_timeout(ctx, game_state);
+        });
+    }
+}
 
-        match message {
-            Message::Text(msg) => {
-                match serde_json::from_str::<GameRequest>(&msg) {
-                    Ok(data) => {
-                        handle_game_data(&state, player_id, &game_id, Json::from(data)).await;
-                    }
-                    Err(_) => {}
+/// Spawn a timer for the current turn deadline unless one is already waiting on it.
+fn schedule_turn_timeout(ctx: &GameContext, game_state: &mut GameState) {
+    game_state.update_turn_deadline();
+    let deadline = match &mut game_state.turn_deadline {
+        Some(deadline) if !deadline.scheduled => deadline,
+        _ => return,
+    };
+    deadline.scheduled = true;
+
+    let (player_id, expires_at) = (deadline.player_id, deadline.expires_at);
+    let handle = ctx.handle().clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(expires_at.saturating_sub(unix_millis()))).await;
+        handle.send(move |game_state, ctx| {
+            let still_waiting = game_state.turn_deadline.as_ref().is_some_and(|deadline| {
+                deadline.player_id == player_id && deadline.expires_at == expires_at
+            });
+            if still_waiting {
+                play_timed_out_turn(game_state, player_id);
+                play_bot_turns(ctx, game_state);
+                schedule_turn_timeout(ctx, game_state);
+                ctx.save(game_state);
+            }
+        });
+    });
+}
+
+/// Announce the auto-start countdown when it is armed or cancelled, and start the game once it runs out.
+fn schedule_auto_start(ctx: &GameContext, game_state: &mut GameState) {
+    if !game_state.update_start_countdown() {
+        return;
+    }
+    let starts_at = match game_state.starts_at {
+        Some(starts_at) => starts_at,
+        None => {
+            broadcast_lobby_settings(game_state, "Countdown cancelled".to_string());
+            return;
+        }
+    };
+    let delay = Duration::from_millis(starts_at.saturating_sub(unix_millis()));
+    let secs = game_state.rules.start_countdown_secs.unwrap_or_default();
+    broadcast_lobby_settings(game_state, format!("Game starts in {} seconds", secs));
+
+    let handle = ctx.handle().clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+        handle.send(move |game_state, ctx| {
+            if game_state.starts_at != Some(starts_at) {
+                return;
+            }
+            game_state.starts_at = None;
+            let host = game_state.host.or_else(|| game_state.players.keys().next().copied());
+            if let Some(host) = host {
+                if let Err(code) = apply_game_request(game_state, host, &GameRequest::new(GameRequestAction::StartGame, None)) {
+                    eprintln!("Error auto-starting game {}: {:?}", game_state.id, code);
                 }
-            },
-            Message::Close(_) => {
             }
-            _ => {}
+            play_bot_turns(ctx, game_state);
+            schedule_turn_timeout(ctx, game_state);
+            ctx.save(game_state);
+        });
+    });
+}
+
+/// Play the turn for a player who let the deadline pass: draw if they have not yet, then discard
+/// the lowest-value card. Kicks the player once they reach the rules' `max_timeouts`.
+pub fn play_timed_out_turn(game_state: &mut GameState, player_id: Uuid) {
+    let needs_draw = match &game_state.game {
+        Some(game) => game.phase == GamePhase::P1,
+        None => return,
+    };
+    if needs_draw {
+        if let Err(code) = apply_game_request(game_state, player_id, &GameRequest::new(GameRequestAction::Draw, None)) {
+            eprintln!("Unable to draw for timed out player {}: {:?}", player_id, code);
         }
     }
 
-    {
-        let mut write_state = state.write().await;
-        if let Some(game_state) = write_state.games.get_mut(&game_id) {
-            game_state.players.remove(&player_id);
-            if let Some(game) = &mut game_state.game {
-                game.remove_player(&player_id).unwrap();
-            }
-            let leave_message = format!("{} left game", player_name.clone());
-            let leave_json = PlayerInfoMessage {
-                message_type: MessageType::PlayerLeft,
-                status: "success".to_string(),
-                data: PlayerInfoData {
-                    players: game_state.players.iter().map(|(_, v)| {
-                        PlayerData {
-                            name: v.0.clone(),
-                            hand: vec![],
-                            bin: vec![],
-                        }
-                    }).collect(),
-                },
-                message: Some(leave_message),
-            };
-            broadcast_message(serde_json::to_string(&leave_json).unwrap().to_string(), game_state).await;
+    let game = game_state.game.as_ref().unwrap();
+    let card = game.players.iter().find(|player| player.id == player_id)
+        .and_then(|player| player.hand.iter().min_by_key(|card| card.points_with(&game.rules)))
+        .map(|card| card.to_string());
+    if let Some(card) = card {
+        if let Err(code) = apply_game_request(game_state, player_id, &GameRequest::new(GameRequestAction::Discard, Some(card))) {
+            eprintln!("Unable to discard for timed out player {}: {:?}", player_id, code);
         }
     }
 
-    send_task.abort();
+    let timeouts = game_state.timeouts.entry(player_id).or_insert(0);
+    *timeouts += 1;
+    if game_state.rules.max_timeouts.is_some_and(|max| *timeouts >= max) {
+        game_state.players[&player_id].send(Message::Close(Some(CloseFrame {
+            code: close_code::POLICY,
+            reason: "Timed out too many turns".into(),
+        })));
+        if let Err(e) = vacate_seat(game_state, &player_id, "timed out") {
+            eprintln!("Unable to free the seat of timed out player {}: {:?}", player_id, e);
+        }
+    }
 }
 
-async fn broadcast_message(message: String, game_state: &mut GameState) {
-    // println!("broadcasting message: {}", message);
-    for (_, (_name, tx)) in game_state.players.iter() {
-        if let Err(e) = tx.send(Message::Text(message.clone().into())) {
-            eprintln!("Error sending message: {:?}", e.to_string());
-        }
+fn schedule_seat_release(ctx: &GameContext, player_id: Uuid, connection_id: Uuid, grace: Duration) {
+    let handle = ctx.handle().clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(grace).await;
+        handle.send(move |game_state, ctx| {
+            let still_away = game_state.players.get(&player_id).is_some_and(|player| {
+                player.connection_id == connection_id && player.sender.is_none()
+            });
+            if still_away {
+                if let Err(e) = vacate_seat(game_state, &player_id, "left game") {
+                    eprintln!("Unable to free the seat of player {}: {:?}", player_id, e);
+                }
+                play_bot_turns(ctx, game_state);
+                schedule_auto_start(ctx, game_state);
+                schedule_turn_timeout(ctx, game_state);
+                ctx.save(game_state);
+            }
+        });
+    });
+}
+
+/// Free a player's seat. When the round cannot let them go, it is left as it was and so is the seat.
+fn vacate_seat(game_state: &mut GameState, player_id: &Uuid, reason: &str) -> Result<(), ErrorCode> {
+    if !game_state.players.contains_key(player_id) {
+        return Ok(());
+    }
+    if let Some(game) = &mut game_state.game {
+        game.remove_player(player_id).map_err(ErrorCode::from)?;
+    }
+    let player = game_state.players.remove(player_id).unwrap();
+    if let Some(game_match) = &mut game_state.game_match {
+        game_match.remove_player(player_id);
     }
+    game_state.rematch_votes.remove(player_id);
+    game_state.ready.remove(player_id);
+    game_state.chat.forget(player_id);
+    broadcast_player_left(game_state, format!("{} {}", player.name, reason));
+    hand_over_host(game_state);
+    check_rematch(game_state);
+    Ok(())
 }
 
-async fn handle_game_data( state: &Arc<RwLock<GameManager>>, player_id: Uuid, game_id : &String, data: Json<GameRequest>) {
-    let mut write_state = state.write().await;
-    let game_state: &mut GameState = write_state.games.get_mut(game_id).unwrap();
-    let game_res: &mut Option<Game> = &mut game_state.game;
+/// The bot whose turn it is, if any.
+fn bot_to_play(game_state: &GameState) -> Option<(Uuid, BotDifficulty)> {
+    if game_state.status != GameStateStatus::InProgress {
+        return None;
+    }
+    let game = game_state.game.as_ref().filter(|game| game.phase != GamePhase::GameEnded)?;
+    // Every seat may have been vacated.
+    let bot_id = game.players.get(game.current_turn)?.id;
+    let difficulty = game_state.players.get(&bot_id)?.bot?;
+    Some((bot_id, difficulty))
+}
 
-    if data.action == GameRequestAction::StartGame {
-        match game_res {
-            None => {
-                let player_list = game_state.players.keys().cloned().collect();
-                let game = Game::new(player_list);
-                game_state.game = Some(game);
-                game_state.status = GameStateStatus::InProgress;
-                let game_event = GameEvent {
-                    event_type: GameEventType::GameStart,
-                    from: None,
-                    to: None,
-                };
-                broadcast_game_message(game_state, game_event);
+/// Let bots take their turns until a human is up or the game ends. Each bot decides on a blocking
+/// thread, since searching for a move can take a while, then plays on the game's task through
+/// `apply_game_request` exactly like a socket request would.
+fn play_bot_turns(ctx: &GameContext, game_state: &GameState) {
+    let (bot_id, difficulty) = match bot_to_play(game_state) {
+        Some(bot) => bot,
+        None => return,
+    };
+    let game = game_state.game.clone().unwrap();
+    let version = game.version;
+    let handle = ctx.handle().clone();
+    tokio::spawn(async move {
+        let decided = tokio::task::spawn_blocking(move || {
+            BotView::new(&game, &bot_id).and_then(|view| difficulty.strategy().decide(&view))
+        }).await;
+        let action = match decided {
+            Ok(Some(action)) => action,
+            Ok(None) => return,
+            Err(e) => return eprintln!("Bot {} failed to decide: {:?}", bot_id, e),
+        };
+        handle.send(move |game_state, ctx| {
+            // The game moved on while the bot was deciding, e.g. its seat was vacated.
+            let current = game_state.game.as_ref().map(|game| game.version);
+            if current != Some(version) || bot_to_play(game_state).map(|(id, _)| id) != Some(bot_id) {
+                return;
+            }
+            let request = match action {
+                BotAction::Draw => GameRequest::new(GameRequestAction::Draw, None),
+                BotAction::TakeBin => GameRequest::new(GameRequestAction::TakeBin, None),
+                BotAction::Discard(card) => GameRequest::new(GameRequestAction::Discard, Some(card.to_string())),
+                BotAction::Close(card) => GameRequest::new(GameRequestAction::Close, Some(card.to_string())),
+            };
+            // A rejected move leaves the turn untouched, stop instead of retrying forever.
+            if let Err(code) = apply_game_request(game_state, bot_id, &request) {
+                eprintln!("Bot {} failed to play its turn: {:?}", bot_id, code);
+                return;
             }
+            play_bot_turns(ctx, game_state);
+            schedule_turn_timeout(ctx, game_state);
+            ctx.save(game_state);
+        });
+    });
+}
 
-            Some(_game) => {
-                let res = GameResponse { status: "failed".to_string(), message_type: MessageType::Reply  };
-                let (_, rx) = game_state.players.get_mut(&player_id).unwrap();
-                if let Err(e) = rx.send(Message::Text(serde_json::to_string(&res).unwrap().into())) {
-                    eprintln!("Error sending message: {:?}", e);
-                }
+fn build_player_info(game_state: &GameState) -> PlayerInfoData {
+    PlayerInfoData {
+        players: game_state.players.values().map(|player| {
+            PlayerData {
+                name: player.name.clone(),
+                is_bot: player.bot.is_some(),
+                hand: vec![],
+                bin: vec![],
             }
+        }).collect(),
+        spectators: game_state.spectators.len(),
+        rules: game_state.rules.clone(),
+        host: game_state.host.and_then(|host| game_state.players.get(&host)).map(|player| player.name.clone()),
+        locked: game_state.locked,
+        ready: game_state.players.iter()
+            .filter(|(id, player)| player.bot.is_some() || game_state.ready.contains(id))
+            .map(|(_, player)| player.name.clone())
+            .collect(),
+        starts_at: game_state.starts_at,
+    }
+}
+
+fn broadcast_player_join(game_state: &mut GameState, message: String) {
+    let msg = ServerMessage::PlayerJoin { data: build_player_info(game_state), message };
+    broadcast_message(&msg, game_state);
+}
+
+fn broadcast_player_left(game_state: &mut GameState, message: String) {
+    let msg = ServerMessage::PlayerLeft { data: build_player_info(game_state), message };
+    broadcast_message(&msg, game_state);
+}
+
+fn broadcast_lobby_settings(game_state: &mut GameState, message: String) {
+    let msg = ServerMessage::LobbySettings { data: build_player_info(game_state), message };
+    broadcast_message(&msg, game_state);
+}
+
+fn broadcast_message(message: &ServerMessage, game_state: &mut GameState) {
+    // Encode once per encoding in use rather than once per connection.
+    let (mut json, mut msgpack) = (None, None);
+    for player in game_state.players.values().chain(game_state.spectators.values()) {
+        let encoded = match player.encoding {
+            Encoding::Json => &mut json,
+            Encoding::MessagePack => &mut msgpack,
         };
-        return;
+        player.send(encoded.get_or_insert_with(|| message.encode(player.encoding)).clone());
     }
+}
 
-    if game_res.is_none() {
-        send_failed_reply(game_state, &player_id);
+fn handle_game_data(game_state: &mut GameState, ctx: &GameContext, player_id: Uuid, data: GameRequest) {
+    // The seat may have been taken away, e.g. after too many timed out turns.
+    if !game_state.players.contains_key(&player_id) {
         return;
-    };
-    let game = game_res.as_mut().unwrap();
-    let player_pos = game.player_pos(&player_id).unwrap();
+    }
+    // Chat is not a move either, and is not persisted.
+    if matches!(data.action, GameRequestAction::Chat | GameRequestAction::Emote) {
+        let result = post_chat(game_state, ctx.services().chat_filter.as_ref(), player_id, &data);
+        reply_to_request(game_state, &player_id, data.request_id, result);
+        return;
+    }
+    // Acknowledgements are not moves, they neither count as activity nor change the game.
+    if matches!(data.action, GameRequestAction::Ack | GameRequestAction::Sync) {
+        let result = sync_game_view(game_state, player_id, &data);
+        reply_to_request(game_state, &player_id, data.request_id, result);
+        return;
+    }
+    game_state.timeouts.remove(&player_id);
+    let result = apply_game_request(game_state, player_id, &data);
+    let refused = result.is_err();
+    reply_to_request(game_state, &player_id, data.request_id, result);
+    // A refused request changed nothing worth saving.
+    if refused {
+        return;
+    }
+    play_bot_turns(ctx, game_state);
+    schedule_auto_start(ctx, game_state);
+    schedule_turn_timeout(ctx, game_state);
+    ctx.save(game_state);
+}
+
+pub fn apply_game_request(game_state: &mut GameState, player_id: Uuid, data: &GameRequest) -> Result<(), ErrorCode> {
+    if data.action == GameRequestAction::AddBot {
+        if game_state.status != GameStateStatus::Lobby {
+            return Err(ErrorCode::GameAlreadyStarted);
+        }
+        if game_state.players.len() >= game_state.rules.max_players {
+            return Err(ErrorCode::GameFull);
+        }
+        seat_bot(game_state, data.difficulty.unwrap_or_default());
+        return Ok(());
+    }
+
+    if data.action == GameRequestAction::Rematch {
+        if game_state.status != GameStateStatus::Finished {
+            return Err(ErrorCode::GameNotFinished);
+        }
+        game_state.rematch_votes.insert(player_id);
+        check_rematch(game_state);
+        return Ok(());
+    }
+
+    if matches!(data.action, GameRequestAction::Ready | GameRequestAction::Unready) {
+        if game_state.status != GameStateStatus::Lobby {
+            return Err(ErrorCode::GameAlreadyStarted);
+        }
+        let name = game_state.players[&player_id].name.clone();
+        let message = if data.action == GameRequestAction::Ready {
+            game_state.ready.insert(player_id);
+            format!("{} is ready", name)
+        } else {
+            game_state.ready.remove(&player_id);
+            format!("{} is not ready", name)
+        };
+        broadcast_lobby_settings(game_state, message);
+        return Ok(());
+    }
+
+    if matches!(data.action, GameRequestAction::Kick | GameRequestAction::TransferHost | GameRequestAction::LockLobby
+        | GameRequestAction::UnlockLobby | GameRequestAction::UpdateRules) {
+        check_host(game_state, player_id)?;
+        return apply_host_action(game_state, player_id, data);
+    }
+
+    if data.action == GameRequestAction::StartGame {
+        check_host(game_state, player_id)?;
+        // Starts the first round from the lobby, or the next round once the previous one ended.
+        let can_start = match (&game_state.game, &game_state.game_match) {
+            (None, _) => true,
+            (Some(game), Some(game_match)) => game.phase == GamePhase::GameEnded && !game_match.is_over(),
+            (Some(_), None) => false,
+        };
+        if !can_start {
+            return Err(ErrorCode::GameAlreadyStarted);
+        }
+        if game_state.game.is_none() && !game_state.all_ready() {
+            return Err(ErrorCode::PlayersNotReady);
+        }
+
+        let game_match = game_state.game_match.get_or_insert_with(|| {
+            GameMatch::new(game_state.players.keys().cloned().collect(), game_state.match_settings.clone())
+        });
+        let mut game = game_match.start_round(game_state.rules.clone());
+        // Versions keep growing across rounds so an old acknowledgement never matches a new view.
+        game.version = game_state.game.as_ref().map_or(0, |previous| previous.version + 1);
+        game_state.game = Some(game);
+        game_state.status = GameStateStatus::InProgress;
+        let game_event = GameEvent {
+            event_type: GameEventType::GameStart,
+            from: None,
+            to: None,
+        };
+        broadcast_game_message(game_state, game_event);
+        return Ok(());
+    }
+
+    let game = game_state.game.as_mut().ok_or(ErrorCode::GameNotStarted)?;
+    let player_pos = game.player_pos(&player_id).ok_or(ErrorCode::NotSeated)?;
     match data.action {
         GameRequestAction::Draw => {
-            match game.draw(&player_id) {
-                Ok(_) => {
-                    let game_event = GameEvent {
-                        event_type: GameEventType::Draw,
-                        from: None,
-                        to: Option::from(player_pos as u8),
-                    };
-                    broadcast_game_message(game_state, game_event);
-                }
-                Err(_) => { send_failed_reply(game_state, &player_id);}
-            }
+            game.draw(&player_id)?;
+            let game_event = GameEvent {
+                event_type: GameEventType::Draw,
+                from: None,
+                to: Option::from(player_pos as u8),
+            };
+            broadcast_game_message(game_state, game_event);
         },
         GameRequestAction::TakeBin => {
-            match game.take_bin(&player_id) {
-                Ok(_) => {
-                    let game_event = GameEvent {
-                        event_type: GameEventType::TakeBin,
-                        from: Option::from(player_pos as u8),
-                        to: Option::from(player_pos as u8),
-                    };
-                    broadcast_game_message(game_state, game_event);
-                }
-                Err(_) => { send_failed_reply(game_state, &player_id);}
-            }
+            game.take_bin(&player_id)?;
+            let game_event = GameEvent {
+                event_type: GameEventType::TakeBin,
+                from: Option::from(player_pos as u8),
+                to: Option::from(player_pos as u8),
+            };
+            broadcast_game_message(game_state, game_event);
         },
         GameRequestAction::Discard => {
-            let card_data = match &data.card {
-                Some(card_data) => card_data,
-                None => {
-                    send_failed_reply(game_state, &player_id);
-                return;
-                }
-            };
-            let card = {
-                match Card::from_string(card_data) {
-                    Some(card) => card,
-                    _ => {
-                        send_failed_reply(game_state, &player_id);
-                        return;
-                    }
-                }
-            };
-            match game.discard(&player_id, card) {
-                Ok(_) => {
-                    if game.phase == GamePhase::GameEnded {
-                        let game_event = GameEvent {
-                            event_type: GameEventType::Close,
-                            from: Option::from(player_pos as u8),
-                            to: Option::from(game.current_turn as u8),
-                        };
-                        broadcast_game_message(game_state, game_event);
-                        game_state.status = GameStateStatus::Finished;
-                        broadcast_end_game_message(game_state);
-                    } else {
-                        let game_event = GameEvent {
-                            event_type: GameEventType::Discard,
-                            from: Option::from(player_pos as u8),
-                            to: Option::from(game.current_turn as u8),
-                        };
-                        broadcast_game_message(game_state, game_event);
-                    }
-                }
-                Err(e) => {
-                    println!("Error discarding game: {:?}", e);
-                    send_failed_reply(game_state, &player_id);
-                }
+            let card = parse_card(data)?;
+            game.discard(&player_id, card)?;
+            if game.phase == GamePhase::GameEnded {
+                let game_event = GameEvent {
+                    event_type: GameEventType::Close,
+                    from: Option::from(player_pos as u8),
+                    to: Option::from(game.current_turn as u8),
+                };
+                broadcast_game_message(game_state, game_event);
+                finish_round(game_state);
+            } else {
+                let game_event = GameEvent {
+                    event_type: GameEventType::Discard,
+                    from: Option::from(player_pos as u8),
+                    to: Option::from(game.current_turn as u8),
+                };
+                broadcast_game_message(game_state, game_event);
             }
         },
         GameRequestAction::Close => {
-            let card_data = match &data.card {
-                Some(card_data) => card_data,
-                None => {
-                    send_failed_reply(game_state, &player_id);
-                    return;
-                }
+            let card = parse_card(data)?;
+            game.close(&player_id, card)?;
+            let game_event = GameEvent {
+                event_type: GameEventType::Close,
+                from: Option::from(player_pos as u8),
+                to: Option::from(game.current_turn as u8),
             };
-            let card = {
-                match Card::from_string(card_data) {
-                    Some(card) => card,
-                    _ => {
-                        send_failed_reply(game_state, &player_id);
-                        return;
-                    }
-                }
-            };
-
-            match game.close(&player_id, card) {
-                Ok(_) => {
-                    let game_event = GameEvent {
-                        event_type: GameEventType::Close,
-                        from: Option::from(player_pos as u8),
-                        to: Option::from(game.current_turn as u8),
-                    };
-                    broadcast_game_message(game_state, game_event);
-                    game_state.status = GameStateStatus::Finished;
-                    broadcast_end_game_message(game_state);
-                }
-                Err(_) => { send_failed_reply(game_state, &player_id);}
-            }
+            broadcast_game_message(game_state, game_event);
+            finish_round(game_state);
         },
         _ => {}
     }
+    Ok(())
+}
+
+/// Broadcast a chat line or emote once it passed the length limit, the rate limit and the chat filter.
+/// Only text lines are kept in the history.
+fn post_chat(game_state: &mut GameState, filter: &dyn ChatFilter, player_id: Uuid, data: &GameRequest) -> Result<(), ErrorCode> {
+    let (text, emote) = match data.action {
+        GameRequestAction::Chat => {
+            let text = data.text.as_deref().map(str::trim).filter(|text| !text.is_empty()).ok_or(ErrorCode::MissingText)?;
+            if text.chars().count() > MAX_CHAT_LENGTH {
+                return Err(ErrorCode::MessageTooLong);
+            }
+            (Some(filter.filter(text).ok_or(ErrorCode::MessageRejected)?), None)
+        }
+        _ => (None, Some(data.emote.ok_or(ErrorCode::MissingEmote)?)),
+    };
+
+    let now = unix_millis();
+    if !game_state.chat.allow(player_id, now) {
+        return Err(ErrorCode::RateLimited);
+    }
+    let line = ChatLine { name: game_state.players[&player_id].name.clone(), text, emote, sent_at: now };
+    if line.text.is_some() {
+        game_state.chat.push(line.clone());
+    }
+    game_state.last_updated = Utc::now();
+    broadcast_message(&ServerMessage::Chat { data: line }, game_state);
+    Ok(())
+}
+
+/// Without a host, for instance in a game restored from before hosts existed, anyone may act as one.
+fn check_host(game_state: &GameState, player_id: Uuid) -> Result<(), ErrorCode> {
+    if game_state.host.is_some_and(|host| host != player_id) {
+        return Err(ErrorCode::NotHost);
+    }
+    Ok(())
+}
+
+fn apply_host_action(game_state: &mut GameState, player_id: Uuid, data: &GameRequest) -> Result<(), ErrorCode> {
+    match data.action {
+        GameRequestAction::Kick => {
+            let target = find_target(game_state, data)?;
+            if target == player_id {
+                return Err(ErrorCode::InvalidTarget);
+            }
+            let kicked = game_state.players[&target].clone();
+            vacate_seat(game_state, &target, "was kicked")?;
+            // Closing the socket ends its connection, which then finds no seat left to release.
+            kicked.send(Message::Close(Some(CloseFrame {
+                code: close_code::POLICY,
+                reason: "Kicked by the host".into(),
+            })));
+        }
+        GameRequestAction::TransferHost => {
+            let target = find_target(game_state, data)?;
+            let player = &game_state.players[&target];
+            if target == player_id || player.bot.is_some() || player.sender.is_none() {
+                return Err(ErrorCode::InvalidTarget);
+            }
+            game_state.host = Some(target);
+            broadcast_lobby_settings(game_state, format!("{} is now the host", player.name));
+        }
+        GameRequestAction::LockLobby => {
+            game_state.locked = true;
+            broadcast_lobby_settings(game_state, "Lobby locked".to_string());
+        }
+        GameRequestAction::UnlockLobby => {
+            game_state.locked = false;
+            broadcast_lobby_settings(game_state, "Lobby unlocked".to_string());
+        }
+        GameRequestAction::UpdateRules => {
+            if game_state.status != GameStateStatus::Lobby {
+                return Err(ErrorCode::GameAlreadyStarted);
+            }
+            let rules = data.rules.clone().ok_or(ErrorCode::MissingRules)?;
+            if rules.validate().is_err() || rules.max_players < game_state.players.len() {
+                return Err(ErrorCode::InvalidRules);
+            }
+            game_state.rules = rules;
+            // Everyone confirms again under the new rules.
+            game_state.ready.clear();
+            broadcast_lobby_settings(game_state, "Rules changed".to_string());
+        }
+        _ => unreachable!("not a host action"),
+    }
+    Ok(())
+}
+
+fn find_target(game_state: &GameState, data: &GameRequest) -> Result<Uuid, ErrorCode> {
+    let name = data.player.as_ref().ok_or(ErrorCode::MissingPlayer)?;
+    game_state.players.iter()
+        .find(|(_, player)| &player.name == name)
+        .map(|(id, _)| *id)
+        .ok_or(ErrorCode::UnknownPlayer)
+}
+
+/// Tell everyone who took over when the host role moved.
+fn hand_over_host(game_state: &mut GameState) {
+    if let Some(host) = game_state.migrate_host() {
+        let message = format!("{} is now the host", game_state.players[&host].name);
+        broadcast_lobby_settings(game_state, message);
+    }
 }
 
+fn seat_bot(game_state: &mut GameState, difficulty: BotDifficulty) {
+    let bot_name = (1..).map(|i| format!("Bot {}", i))
+        .find(|name| !game_state.players.values().any(|p| &p.name == name))
+        .unwrap();
+    game_state.players.insert(Uuid::new_v4(), PlayerConnection {
+        name: bot_name.clone(),
+        sender: None,
+        connection_id: Uuid::new_v4(),
+        bot: Some(difficulty),
+        encoding: Default::default(),
+        updates: Default::default(),
+        views: Default::default(),
+    });
+    broadcast_player_join(game_state, format!("{} joined game", bot_name));
+}
+
+fn parse_card(data: &GameRequest) -> Result<Card, ErrorCode> {
+    let card_data = data.card.as_ref().ok_or(ErrorCode::MissingCard)?;
+    Card::from_string(card_data).ok_or(ErrorCode::UnknownCard)
+}
+
+/// Reply when the request failed or asked for it with a `request_id`.
+fn reply_to_request(game_state: &GameState, player_id: &Uuid, request_id: Option<String>, result: Result<(), ErrorCode>) {
+    match result {
+        Ok(()) if request_id.is_some() => send_reply(game_state, player_id, request_id, None),
+        Ok(()) => {}
+        Err(code) => send_reply(game_state, player_id, request_id, Some(code)),
+    }
+}
 
-fn send_failed_reply(game_state: &mut GameState, player_id: &Uuid) {
-    let res = GameResponse { status: "failed".to_string(), message_type: MessageType::Reply };
-    let (_, rx) = game_state.players.get_mut(player_id).unwrap();
-    if let Err(e) = rx.send(Message::Text(serde_json::to_string(&res).unwrap().into())) {
-        eprintln!("Error sending message: {:?}", e);
+/// Acknowledge the view of a delta socket, or send a snapshot on `sync` or when the
+/// acknowledged version was never sent.
+fn sync_game_view(game_state: &mut GameState, player_id: Uuid, data: &GameRequest) -> Result<(), ErrorCode> {
+    let game = game_state.game.as_ref().ok_or(ErrorCode::GameNotStarted)?;
+    if data.action == GameRequestAction::Ack {
+        let version = data.version.ok_or(ErrorCode::MissingVersion)?;
+        let player = game_state.players.get_mut(&player_id).unwrap();
+        if player.updates == UpdateMode::Full || player.views.ack(version) {
+            return Ok(());
+        }
+    }
+    let game_event = GameEvent {
+        event_type: GameEventType::Sync,
+        from: None,
+        to: None,
+    };
+    let view = build_game_data(&player_id, game, game_state, game_event);
+    send_game_view(game_state.players.get_mut(&player_id).unwrap(), view, true);
+    Ok(())
+}
+
+fn send_reply(game_state: &GameState, player_id: &Uuid, request_id: Option<String>, error: Option<ErrorCode>) {
+    if let Some(player) = game_state.players.get(player_id) {
+        player.send_message(&ServerMessage::reply(request_id, error));
+    }
+}
+
+/// Send the rematch vote count, and go back to the lobby once every connected player voted.
+fn check_rematch(game_state: &mut GameState) {
+    if game_state.status != GameStateStatus::Finished || game_state.rematch_votes.is_empty() {
+        return;
+    }
+
+    let voters: Vec<&Uuid> = game_state.connected_players().map(|(id, _)| id).collect();
+    let accepted = voters.iter().all(|id| game_state.rematch_votes.contains(id));
+    let msg = ServerMessage::Rematch {
+        data: RematchData {
+            votes: voters.iter().filter(|id| game_state.rematch_votes.contains(id))
+                .map(|id| game_state.players[id].name.clone())
+                .collect(),
+            needed: voters.len(),
+            accepted,
+        },
+    };
+
+    if accepted {
+        game_state.reset_to_lobby();
+    }
+    broadcast_message(&msg, game_state);
+}
+
+/// Add the ended round to the match totals, the game state only finishes with the match.
+fn finish_round(game_state: &mut GameState) {
+    let game = game_state.game.as_ref().unwrap();
+    game_state.round_logs.push(game.log.clone());
+    let match_over = match &mut game_state.game_match {
+        Some(game_match) => {
+            game_match.record_round(game);
+            game_match.is_over()
+        }
+        None => true,
+    };
+    if match_over {
+        game_state.status = GameStateStatus::Finished;
     }
+    broadcast_end_game_message(game_state);
+    if let Some(msg) = build_match_score_message(game_state) {
+        broadcast_message(&msg, game_state);
+    }
+}
+
+fn build_match_score_message(game_state: &GameState) -> Option<ServerMessage> {
+    let game_match = game_state.game_match.as_ref()?;
+    let name = |id: &Uuid| game_state.players.get(id).map(|player| player.name.clone());
+
+    Some(ServerMessage::MatchScore {
+        data: MatchScoreData {
+            rounds_played: game_match.rounds_played,
+            rounds: game_match.settings.rounds,
+            target_score: game_match.settings.target_score,
+            finished: game_match.is_over(),
+            winner_name: game_match.winner().and_then(|id| name(&id)),
+            players: game_match.seats.iter().filter_map(|id| {
+                Some(MatchScoreEntry {
+                    name: name(id)?,
+                    total: game_match.totals[id],
+                    eliminated: game_match.eliminated.contains(id),
+                })
+            }).collect(),
+        },
+    })
 }
 
 fn broadcast_end_game_message(game_state: &mut GameState) {
+    let msg = build_end_game_message(game_state);
+    broadcast_message(&msg, game_state);
+}
+
+fn build_end_game_message(game_state: &GameState) -> ServerMessage {
     let game = game_state.game.as_ref().unwrap();
     let scores = game.players.iter().map(|player|  {
         EndGameScores {
-            name: game_state.players[&player.id].0.clone(),
-            score: player.score(),
+            name: game_state.players[&player.id].name.clone(),
+            score: player.score_with(&game.rules),
             hand: player.hand.iter().map(|card|card.to_string()).collect(),
         }
     }).collect();
     let winner = game.winner();
-    let msg = EndGameMessage {
-        status: "success".to_string(),
-        message_type: MessageType::EndGame,
+    ServerMessage::EndGame {
         data: EndGameData {
             winner_name: if let Some(winner) = winner {
-                let name = game_state.players[&winner.id].0.clone();
+                let name = game_state.players[&winner.id].name.clone();
                 Some(name)
             }else {
                 None
             },
             players: scores
         },
-    };
-
-    for (_, (_name, con)) in game_state.players.iter() {
-        if let Err(e) = con.send(Message::Text(serde_json::to_string(&msg).unwrap().into())) {
-            eprintln!("Error sending message: {:?}", e);
-        }
     }
-
 }
-fn broadcast_game_message(game_state: &mut GameState, game_event: GameEvent) {
+pub fn broadcast_game_message(game_state: &mut GameState, game_event: GameEvent) {
+        game_state.update_turn_deadline();
         let game = game_state.game.as_ref().unwrap();
-        for (id, (_name, con)) in game_state.players.iter() {
-            let msg = build_game_message(id, game, game_state, game_event.clone());
-
-            if let Err(e) = con.send(Message::Text(serde_json::to_string(&msg).unwrap().into())) {
-                eprintln!("Error sending message: {:?}", e);
-            }
+        let views: Vec<(Uuid, GameData)> = game_state.players.iter()
+            .filter(|(_, player)| player.sender.is_some())
+            .map(|(id, _)| (*id, build_game_data(id, game, game_state, game_event.clone())))
+            .collect();
+        // Spectators have no seat, so every hand in their message is masked.
+        for (id, spectator) in game_state.spectators.iter() {
+            let data = build_game_data(id, game, game_state, game_event.clone());
+            spectator.send_message(&ServerMessage::GameEvent { data });
+        }
+        for (id, view) in views {
+            send_game_view(game_state.players.get_mut(&id).unwrap(), view, false);
         }
 }
 
-fn build_game_message(id: &Uuid, game: &Game, game_state: &GameState, game_event: GameEvent) -> GameMessage {
-    let player_pos = match game.player_pos(&id){
-        None => {panic!("Player {} not found", id)}
-        Some(i) => {i as u8}
+/// Send a view of the game, as a delta when the socket asked for them unless a full
+/// `snapshot` is needed.
+fn send_game_view(player: &mut PlayerConnection, view: GameData, snapshot: bool) {
+    let msg = match player.updates {
+        UpdateMode::Full => ServerMessage::GameEvent { data: view },
+        UpdateMode::Delta if snapshot => player.views.snapshot(view),
+        UpdateMode::Delta => player.views.update(view),
     };
+    player.send_message(&msg);
+}
+
+fn build_game_data(id: &Uuid, game: &Game, game_state: &GameState, game_event: GameEvent) -> GameData {
+    let player_pos = game.player_pos(id).map(|i| i as u8);
 
     let mut players = vec![];
 
     for i in 0..game.players.len() {
         let p_id = game.players[i].id;
-        let (name,_) = game_state.players.get(&p_id).unwrap();
+        let player = game_state.players.get(&p_id).unwrap();
         players.push(PlayerData {
-            name: name.to_string(),
+            name: player.name.to_string(),
+            is_bot: player.bot.is_some(),
             hand: {
                 if p_id  == *id {
                     game.players[i].hand.iter().map(|card| card.to_string()).collect()
                 } else {
-                    vec!["".to_string();4]
+                    vec!["".to_string(); game.rules.hand_size]
                 }
 
             },
@@ -492,23 +1538,17 @@ fn build_game_message(id: &Uuid, game: &Game, game_state: &GameState, game_event
     }
 
 
-    let game_data =  GameData{
-        player_id: id.clone(),
+    GameData {
+        version: game.version,
+        player_id: *id,
         player_pos,
         num_of_players: game_state.players.len() as u8,
         card_left: game.card_left(),
         current_turn: game.current_turn as u8,
         current_phase: game.phase.clone(),
+        turn_deadline: game_state.turn_deadline.as_ref().map(|deadline| deadline.expires_at),
+        rules: game.rules.clone(),
         event: game_event,
         players,
-    };
-
-    let msg = GameMessage{
-        message_type: MessageType::GameEvent,
-        status: "success".to_string(),
-        message: None,
-        data: Some(game_data),
-    };
-
-    msg
-}
+    }
+}
\ No newline at end of file
@@ -0,0 +1,114 @@
+use crate::handlers::error::GameError;
+use crate::handlers::game::spawn_send_task;
+use crate::protocol::{Encoding, LobbySummary, ServerMessage};
+use crate::state::state::GameManager;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::response::IntoResponse;
+use axum::Json;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+const MAX_PER_PAGE: usize = 100;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct LobbyQuery {
+    /// First page is `1`.
+    pub page: usize,
+    pub per_page: usize,
+    min_free_seats: Option<usize>,
+    max_players: Option<usize>,
+    hand_size: Option<usize>,
+    password_protected: Option<bool>,
+}
+
+impl Default for LobbyQuery {
+    fn default() -> Self {
+        LobbyQuery {
+            page: 1,
+            per_page: 20,
+            min_free_seats: None,
+            max_players: None,
+            hand_size: None,
+            password_protected: None,
+        }
+    }
+}
+
+impl LobbyQuery {
+    /// Refuse empty pages, and cap `per_page` at `MAX_PER_PAGE`.
+    pub fn validate(mut self) -> Result<Self, String> {
+        if self.page == 0 {
+            return Err("page starts at 1".to_string());
+        }
+        if self.per_page == 0 {
+            return Err("per_page must be at least 1".to_string());
+        }
+        self.per_page = self.per_page.min(MAX_PER_PAGE);
+        Ok(self)
+    }
+
+    /// How many matching lobbies come before the requested page.
+    pub fn offset(&self) -> usize {
+        self.page.saturating_sub(1).saturating_mul(self.per_page)
+    }
+
+    fn matches(&self, lobby: &LobbySummary) -> bool {
+        self.min_free_seats.is_none_or(|seats| lobby.max_players.saturating_sub(lobby.players) >= seats)
+            && self.max_players.is_none_or(|max_players| lobby.max_players == max_players)
+            && self.hand_size.is_none_or(|hand_size| lobby.rules.hand_size == hand_size)
+            && self.password_protected.is_none_or(|protected| lobby.password_protected == protected)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LobbyListResponse {
+    lobbies: Vec<LobbySummary>,
+    /// Matching lobbies over all pages.
+    total: usize,
+    page: usize,
+    per_page: usize,
+}
+
+/// List public lobbies with a free seat, newest first.
+pub async fn list_lobbies(State(state): State<Arc<RwLock<GameManager>>>, Query(query): Query<LobbyQuery>) -> Result<Json<LobbyListResponse>, GameError> {
+    let query = query.validate().map_err(GameError::InvalidOperation)?;
+    let lobbies: Vec<LobbySummary> = state.read().await.services.lobby_feed().lobbies().into_iter()
+        .filter(|lobby| query.matches(lobby))
+        .collect();
+    let total = lobbies.len();
+    let lobbies = lobbies.into_iter().skip(query.offset()).take(query.per_page).collect();
+    Ok(Json(LobbyListResponse { lobbies, total, page: query.page, per_page: query.per_page }))
+}
+
+pub async fn lobby_feed(ws: WebSocketUpgrade, State(state): State<Arc<RwLock<GameManager>>>) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| handle_lobby_feed(socket, state))
+}
+
+/// Send the current lobby list, then every change to it until the socket closes.
+async fn handle_lobby_feed(socket: WebSocket, state: Arc<RwLock<GameManager>>) {
+    let (sender, mut receiver) = socket.split();
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<Message>();
+    let send_task = spawn_send_task(sender, rx);
+    let watcher_id = Uuid::new_v4();
+
+    {
+        let game_manager = state.read().await;
+        let mut lobby_feed = game_manager.services.lobby_feed();
+        let lobby_list = ServerMessage::LobbyList { data: lobby_feed.lobbies() };
+        if let Err(e) = tx.send(lobby_list.encode(Encoding::Json)) {
+            eprintln!("Error sending message: {:?}", e);
+        }
+        lobby_feed.watchers.insert(watcher_id, tx);
+    }
+
+    // The feed is read only, messages are read to notice the socket closing.
+    while let Some(Ok(_)) = receiver.next().await {}
+
+    state.read().await.services.lobby_feed().watchers.remove(&watcher_id);
+    send_task.abort();
+}
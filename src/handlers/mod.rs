@@ -1,2 +1,5 @@
 pub mod game;
-pub mod error;
\ No newline at end of file
+pub mod error;
+pub mod lobby;
+pub mod cluster;
+mod test;
\ No newline at end of file
@@ -0,0 +1,171 @@
+#[cfg(test)]
+mod tests {
+    use crate::engine::bot::BotDifficulty;
+    use crate::engine::game::{GameError, GamePhase};
+    use crate::engine::rules::RuleSet;
+    use crate::handlers::game::is_admin;
+    use crate::handlers::lobby::LobbyQuery;
+    use crate::protocol::*;
+    use axum::extract::ws::Message;
+    use axum::http::header::AUTHORIZATION;
+    use axum::http::HeaderMap;
+    use schemars::gen::SchemaSettings;
+    use serde_json::{json, Value};
+    use std::fs;
+    use std::path::Path;
+    use ts_rs::TS;
+
+    #[test]
+    fn test_negotiate_protocol() {
+        assert_eq!(negotiate_protocol(None), Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate_protocol(Some(&PROTOCOL_VERSION.to_string())), Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate_protocol(Some(&"200".to_string())), Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate_protocol(Some(&"0".to_string())), None);
+        assert_eq!(negotiate_protocol(Some(&"latest".to_string())), None);
+    }
+
+    #[test]
+    fn test_lobby_query() {
+        let query = |query: &str| serde_urlencoded::from_str::<LobbyQuery>(query).unwrap().validate();
+        assert!(query("page=0").is_err());
+        assert!(query("per_page=0").is_err());
+        assert_eq!(query("").unwrap().offset(), 0);
+        assert_eq!(query("page=3&per_page=10").unwrap().offset(), 20);
+
+        // Large values are capped rather than overflowing.
+        let large = query(&format!("page={}&per_page={}", usize::MAX, usize::MAX)).unwrap();
+        assert_eq!(large.per_page, 100);
+        assert_eq!(large.offset(), usize::MAX);
+    }
+
+    fn decode(message: Message) -> Value {
+        match message {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            Message::Binary(bytes) => rmp_serde::from_slice(&bytes).unwrap(),
+            _ => panic!("not a data frame"),
+        }
+    }
+
+    #[test]
+    fn test_is_admin() {
+        let mut headers = HeaderMap::new();
+        assert!(!is_admin(Some("secret"), &headers));
+        headers.insert(AUTHORIZATION, "Bearer secret".parse().unwrap());
+        assert!(is_admin(Some("secret"), &headers));
+        assert!(!is_admin(Some("other"), &headers));
+        assert!(!is_admin(None, &headers));
+    }
+
+    #[test]
+    fn test_reply_message() {
+        let reply = decode(ServerMessage::reply(Some("42".to_string()), Some(ErrorCode::from(GameError::ScoreTooLow))).encode(Encoding::Json));
+        assert_eq!(reply["message_type"], "reply");
+        assert_eq!(reply["status"], "failed");
+        assert_eq!(reply["request_id"], "42");
+        assert_eq!(reply["error"]["code"], "score_too_low");
+
+        let reply = decode(ServerMessage::reply(None, None).encode(Encoding::Json));
+        assert_eq!(reply["status"], "success");
+        assert_eq!(reply["error"], Value::Null);
+
+        let request: GameRequest = serde_json::from_str(r#"{"action":"discard","card":"H2","request_id":"7"}"#).unwrap();
+        assert_eq!(request.action, GameRequestAction::Discard);
+        assert_eq!(request.request_id.as_deref(), Some("7"));
+    }
+
+    #[test]
+    fn test_message_pack_encoding() {
+        assert_eq!(Encoding::from_param(None), Some(Encoding::Json));
+        assert_eq!(Encoding::from_param(Some(&"msgpack".to_string())), Some(Encoding::MessagePack));
+        assert_eq!(Encoding::from_param(Some(&"cbor".to_string())), None);
+
+        let message = ServerMessage::Rematch { data: RematchData { votes: vec!["Alice".to_string()], needed: 2, accepted: false } };
+        let binary = message.encode(Encoding::MessagePack);
+        assert!(matches!(binary, Message::Binary(_)));
+        assert_eq!(decode(binary), decode(message.encode(Encoding::Json)));
+
+        let session = ServerMessage::Session {
+            data: SessionData { player_id: uuid::Uuid::new_v4(), token: "token".to_string(), protocol_version: PROTOCOL_VERSION },
+        };
+        assert_eq!(decode(session.encode(Encoding::MessagePack)), decode(session.encode(Encoding::Json)));
+
+        let reply = ServerMessage::reply(Some("1".to_string()), Some(ErrorCode::EmptyBin));
+        assert_eq!(decode(reply.encode(Encoding::MessagePack)), decode(reply.encode(Encoding::Json)));
+
+        let bytes = rmp_serde::to_vec_named(&json!({"action": "take_bin", "request_id": "3"})).unwrap();
+        let value: Value = rmp_serde::from_slice(&bytes).unwrap();
+        let request: GameRequest = serde_json::from_value(value).unwrap();
+        assert_eq!(request.action, GameRequestAction::TakeBin);
+        assert_eq!(request.request_id.as_deref(), Some("3"));
+    }
+
+    /// Regenerate with `UPDATE_SCHEMA=1 cargo test protocol_schema`.
+    #[test]
+    fn test_protocol_schema() {
+        let generated = [("protocol.schema.json", json_schema()), ("protocol.d.ts", typescript())];
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("schema");
+
+        for (file, content) in generated {
+            let path = dir.join(file);
+            if std::env::var_os("UPDATE_SCHEMA").is_some() {
+                fs::create_dir_all(&dir).unwrap();
+                fs::write(&path, &content).unwrap();
+            }
+            let committed = fs::read_to_string(&path).unwrap_or_default();
+            assert!(committed == content, "schema/{} is out of date, run `UPDATE_SCHEMA=1 cargo test protocol_schema`", file);
+        }
+    }
+
+    fn json_schema() -> String {
+        let mut generator = SchemaSettings::draft07().into_generator();
+        let client_message = generator.subschema_for::<GameRequest>();
+        let server_message = generator.subschema_for::<ServerEnvelope>();
+        let schema = json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "title": "Fortyone WebSocket protocol",
+            "protocol_version": PROTOCOL_VERSION,
+            "client_message": client_message,
+            "server_message": server_message,
+            "definitions": generator.definitions(),
+        });
+        serde_json::to_string_pretty(&schema).unwrap() + "\n"
+    }
+
+    fn typescript() -> String {
+        let declarations = [
+            GameRequest::decl(),
+            GameRequestAction::decl(),
+            BotDifficulty::decl(),
+            Emote::decl(),
+            ServerEnvelope::decl(),
+            Status::decl(),
+            ServerMessage::decl(),
+            SessionData::decl(),
+            PlayerInfoData::decl(),
+            PlayerData::decl(),
+            GameData::decl(),
+            GameDeltaData::decl(),
+            PlayerDelta::decl(),
+            GamePhase::decl(),
+            RuleSet::decl(),
+            GameEvent::decl(),
+            GameEventType::decl(),
+            EndGameData::decl(),
+            EndGameScores::decl(),
+            MatchScoreData::decl(),
+            MatchScoreEntry::decl(),
+            RematchData::decl(),
+            LobbySummary::decl(),
+            LobbyRemovedData::decl(),
+            QueueData::decl(),
+            ChatLine::decl(),
+            ErrorData::decl(),
+            ErrorCode::decl(),
+        ];
+        let mut output = format!("// Generated from the Rust protocol types, protocol version {}. Do not edit.\n", PROTOCOL_VERSION);
+        for declaration in declarations {
+            output += &format!("\nexport {}\n", declaration);
+        }
+        output
+    }
+}
\ No newline at end of file
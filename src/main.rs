@@ -1,7 +1,12 @@
 use crate::config::Config;
+use crate::handlers::cluster::run_cluster_node;
+use crate::handlers::game::{collect_expired_games, release_restored_seats, resume_turn_timers, shut_down};
 use crate::routes::game::create_router;
+use crate::state::cluster::{ClusterBackend, ClusterNode, LocalCluster};
 use crate::state::state::GameManager;
+use crate::state::storage::{GameRepository, InMemoryRepository, JsonFileRepository};
 use axum::{serve};
+use http::header::AUTHORIZATION;
 use http::HeaderValue;
 use std::net::SocketAddr;
 use std::sync::Arc;
@@ -11,6 +16,7 @@ use tower_http::cors::{Any, CorsLayer};
 
 mod engine;
 mod state;
+mod protocol;
 mod handlers;
 mod routes;
 mod config;
@@ -26,21 +32,78 @@ async fn main() {
         CorsLayer::new()
             .allow_origin(Any)
             .allow_credentials(true)
+            .allow_headers([AUTHORIZATION])
     } else {
         let allowed_origin =  config.allowed_origin.parse::<HeaderValue>().unwrap();
         CorsLayer::new()
             .allow_origin(allowed_origin)
             .allow_credentials(true)
+            .allow_headers([AUTHORIZATION])
     };
 
-    let addr: SocketAddr = config
-        .server_address
+    let repository: Box<dyn GameRepository> = match &config.storage_path {
+        Some(path) => Box::new(JsonFileRepository::open(path).expect("Unable to open storage file")),
+        None => {
+            eprintln!("STORAGE_PATH is not set, games are lost when the server stops");
+            Box::new(InMemoryRepository::default())
+        }
+    };
+    // Every node of this process shares the in-memory backend.
+    let backend: Arc<dyn ClusterBackend> = Arc::new(LocalCluster::default());
+    let extra_nodes: Vec<_> = config.local_cluster_addresses.iter().enumerate().map(|(i, address)| {
+        let cluster = Arc::new(ClusterNode::new(format!("{}-{}", config.node_id, i + 1), backend.clone()));
+        // Only the main node uses the storage file.
+        let repository = Box::new(InMemoryRepository::default());
+        tokio::spawn(run_node(config.clone(), address.clone(), repository, cluster, cors.clone()))
+    }).collect();
+
+    let cluster = Arc::new(ClusterNode::new(config.node_id.clone(), backend));
+    run_node(config.clone(), config.server_address.clone(), repository, cluster, cors).await;
+    for node in extra_nodes {
+        node.await.unwrap();
+    }
+}
+
+/// Serve one node until it is shut down.
+async fn run_node(config: Config, address: String, repository: Box<dyn GameRepository>, cluster: Arc<ClusterNode>, cors: CorsLayer) {
+    let addr: SocketAddr = address
         .parse()
         .expect("Invalid server address format");
     let listener = TcpListener::bind(addr).await.unwrap();
-    let game_state = Arc::new(RwLock::new(GameManager::new()));
-    let router = create_router(game_state, cors);
-    println!("Listening on {}", addr);
-    serve(listener, router.into_make_service()).await.unwrap();
+    let node_id = cluster.id.clone();
+    let game_state = Arc::new(RwLock::new(GameManager::new(&config, repository, cluster)));
+    tokio::spawn(run_cluster_node(game_state.clone()));
+    release_restored_seats(game_state.clone()).await;
+    resume_turn_timers(game_state.clone()).await;
+    tokio::spawn(collect_expired_games(game_state.clone()));
+    let router = create_router(game_state.clone(), cors);
+    println!("Listening on {} as node {}", addr, node_id);
+    serve(listener, router.into_make_service())
+        .with_graceful_shutdown(shutdown_signal(game_state))
+        .await
+        .unwrap();
     // tokio::net::windows::named_pipe::PipeEnd(&addr).serve(router.into_make_service()).await.unwrap();
 }
+
+/// Resolve on Ctrl+C or SIGTERM, once every game is persisted and every client was told to reconnect.
+async fn shutdown_signal(state: Arc<RwLock<GameManager>>) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("Unable to listen for Ctrl+C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Unable to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    println!("Shutting down");
+    shut_down(state).await;
+}
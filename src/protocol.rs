@@ -0,0 +1,551 @@
+use crate::engine::bot::BotDifficulty;
+use crate::engine::game::{GameError, GamePhase};
+use crate::engine::rules::RuleSet;
+use axum::extract::ws::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Version of the WebSocket protocol spoken by this server.
+pub const PROTOCOL_VERSION: u8 = 1;
+/// Oldest protocol version a client may still negotiate.
+pub const MIN_PROTOCOL_VERSION: u8 = 1;
+
+/// Pick the protocol version for a client that speaks up to `requested`, defaults to the
+/// current version. `None` when the client is too old.
+pub fn negotiate_protocol(requested: Option<&String>) -> Option<u8> {
+    let requested = match requested {
+        Some(version) => version.parse::<u8>().ok()?,
+        None => PROTOCOL_VERSION,
+    };
+    (requested >= MIN_PROTOCOL_VERSION).then(|| requested.min(PROTOCOL_VERSION))
+}
+
+/// How messages are encoded on a socket. MessagePack messages go out as binary frames with
+/// the same field names as the JSON ones.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Encoding {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+impl Encoding {
+    /// Read the `encoding` query parameter, JSON when it is missing.
+    pub fn from_param(param: Option<&String>) -> Option<Encoding> {
+        match param.map(String::as_str) {
+            None | Some("json") => Some(Encoding::Json),
+            Some("msgpack") => Some(Encoding::MessagePack),
+            _ => None,
+        }
+    }
+}
+
+/// How game state reaches a socket. In delta mode a `game_delta` with only the changes since
+/// the last acknowledged version is sent instead of a full `game_event` whenever possible.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum UpdateMode {
+    #[default]
+    Full,
+    Delta,
+}
+
+impl UpdateMode {
+    /// Read the `updates` query parameter, full snapshots when it is missing.
+    pub fn from_param(param: Option<&String>) -> Option<UpdateMode> {
+        match param.map(String::as_str) {
+            None | Some("full") => Some(UpdateMode::Full),
+            Some("delta") => Some(UpdateMode::Delta),
+            _ => None,
+        }
+    }
+}
+
+/// What a player socket negotiated through the query parameters of join and rejoin.
+#[derive(Debug, Clone, Copy)]
+pub struct SocketOptions {
+    pub protocol_version: u8,
+    pub encoding: Encoding,
+    pub updates: UpdateMode,
+}
+
+impl SocketOptions {
+    pub fn from_params(params: &HashMap<String, String>) -> Result<SocketOptions, &'static str> {
+        Ok(SocketOptions {
+            protocol_version: negotiate_protocol(params.get("protocol")).ok_or("Unsupported protocol version.")?,
+            encoding: Encoding::from_param(params.get("encoding")).ok_or("Unsupported encoding.")?,
+            updates: UpdateMode::from_param(params.get("updates")).ok_or("Unsupported update mode.")?,
+        })
+    }
+}
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GameRequestAction {
+    StartGame,
+    AddBot,
+    Rematch,
+    Draw,
+    TakeBin,
+    Discard,
+    Close,
+    /// Confirm the game state at `version` was applied, later deltas are based on it.
+    Ack,
+    /// Ask for a full snapshot.
+    Sync,
+    /// Mark yourself ready to start, or no longer ready.
+    Ready,
+    Unready,
+    /// Host only: remove `player` from the game.
+    Kick,
+    /// Host only: hand the host role to `player`.
+    TransferHost,
+    /// Host only: stop new players from joining.
+    LockLobby,
+    UnlockLobby,
+    /// Host only: replace the rules with `rules` before the game starts.
+    UpdateRules,
+    /// Send `text` to everyone in the game, in the lobby or while playing.
+    Chat,
+    /// Send a predefined `emote` reaction.
+    Emote,
+}
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Emote {
+    ThumbsUp,
+    Laugh,
+    Wow,
+    Sad,
+    Angry,
+    GoodGame,
+}
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Deserialize)]
+pub struct GameRequest {
+    pub action: GameRequestAction,
+    #[cfg_attr(test, ts(optional))]
+    pub card: Option<String>,
+    #[cfg_attr(test, ts(optional))]
+    pub difficulty: Option<BotDifficulty>,
+    /// Echoed in the reply to this request.
+    #[cfg_attr(test, ts(optional))]
+    pub request_id: Option<String>,
+    #[cfg_attr(test, ts(optional, type = "number"))]
+    pub version: Option<u64>,
+    /// Name of the player a host action applies to.
+    #[cfg_attr(test, ts(optional))]
+    pub player: Option<String>,
+    #[cfg_attr(test, ts(optional))]
+    pub rules: Option<RuleSet>,
+    /// Chat line to send.
+    #[cfg_attr(test, ts(optional))]
+    pub text: Option<String>,
+    #[cfg_attr(test, ts(optional))]
+    pub emote: Option<Emote>,
+}
+
+impl GameRequest {
+    pub fn new(action: GameRequestAction, card: Option<String>) -> Self {
+        GameRequest { action, card, difficulty: None, request_id: None, version: None, player: None, rules: None, text: None, emote: None }
+    }
+}
+
+/// Machine-readable reason of a failed reply.
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    MalformedRequest,
+    NotSeated,
+    GameNotStarted,
+    GameAlreadyStarted,
+    GameNotFinished,
+    GameFull,
+    MissingCard,
+    UnknownCard,
+    MissingVersion,
+    NotHost,
+    PlayersNotReady,
+    MissingText,
+    MessageTooLong,
+    MessageRejected,
+    MissingEmote,
+    RateLimited,
+    MissingPlayer,
+    UnknownPlayer,
+    InvalidTarget,
+    MissingRules,
+    InvalidRules,
+    NotYourTurn,
+    InvalidMove,
+    InvalidPlayer,
+    CardNotInHand,
+    ScoreTooLow,
+    CloseNotAllowed,
+    EmptyBin,
+}
+
+impl ErrorCode {
+    pub fn message(&self) -> &'static str {
+        match self {
+            ErrorCode::MalformedRequest => "Request could not be parsed",
+            ErrorCode::NotSeated => "You have no seat in this round",
+            ErrorCode::GameNotStarted => "The game has not started",
+            ErrorCode::GameAlreadyStarted => "The game already started",
+            ErrorCode::GameNotFinished => "The game is not finished",
+            ErrorCode::GameFull => "Max player has been reached",
+            ErrorCode::MissingCard => "This action needs a card",
+            ErrorCode::UnknownCard => "Unknown card symbol",
+            ErrorCode::MissingVersion => "This action needs a version",
+            ErrorCode::NotHost => "Only the host can do this",
+            ErrorCode::PlayersNotReady => "Not every player is ready",
+            ErrorCode::MissingText => "This action needs a text",
+            ErrorCode::MessageTooLong => "This message is too long",
+            ErrorCode::MessageRejected => "This message is not allowed",
+            ErrorCode::MissingEmote => "This action needs an emote",
+            ErrorCode::RateLimited => "You are sending messages too fast",
+            ErrorCode::MissingPlayer => "This action needs a player",
+            ErrorCode::UnknownPlayer => "No player with this name",
+            ErrorCode::InvalidTarget => "This action cannot target this player",
+            ErrorCode::MissingRules => "This action needs rules",
+            ErrorCode::InvalidRules => "These rules are not valid for this lobby",
+            ErrorCode::NotYourTurn => "It is not your turn",
+            ErrorCode::InvalidMove => "This move is not allowed in the current phase",
+            ErrorCode::InvalidPlayer => "Unknown player",
+            ErrorCode::CardNotInHand => "The card is not in your hand",
+            ErrorCode::ScoreTooLow => "Your score is below the close threshold",
+            ErrorCode::CloseNotAllowed => "Closing is disabled by the rules",
+            ErrorCode::EmptyBin => "Your discard bin is empty",
+        }
+    }
+}
+
+impl From<GameError> for ErrorCode {
+    fn from(error: GameError) -> Self {
+        match error {
+            GameError::InvalidPlayer => ErrorCode::InvalidPlayer,
+            GameError::InvalidTurn | GameError::NotYourTurn => ErrorCode::NotYourTurn,
+            GameError::InvalidMove | GameError::LogMismatch => ErrorCode::InvalidMove,
+            GameError::CardNotFound => ErrorCode::CardNotInHand,
+            GameError::ScoreTooLow => ErrorCode::ScoreTooLow,
+            GameError::CloseNotAllowed => ErrorCode::CloseNotAllowed,
+            GameError::EmptyBin => ErrorCode::EmptyBin,
+        }
+    }
+}
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorData {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+/// Every message the server sends over the socket, tagged by `message_type`.
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Serialize)]
+#[serde(tag = "message_type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Session { data: SessionData },
+    PlayerJoin { data: PlayerInfoData, message: String },
+    PlayerLeft { data: PlayerInfoData, message: String },
+    /// The host, the lock, the rules or the readiness of the lobby changed.
+    LobbySettings { data: PlayerInfoData, message: String },
+    /// Answer to a request, sent when it failed or carried a `request_id`.
+    Reply { request_id: Option<String>, error: Option<ErrorData> },
+    GameEvent { data: GameData },
+    GameDelta { data: GameDeltaData },
+    EndGame { data: EndGameData },
+    MatchScore { data: MatchScoreData },
+    Rematch { data: RematchData },
+    /// Every public lobby, first message of the lobby feed.
+    LobbyList { data: Vec<LobbySummary> },
+    LobbyUpdate { data: LobbySummary },
+    /// The game started, became full or went away.
+    LobbyRemoved { data: LobbyRemovedData },
+    /// Sent to quick-match players while they wait for a table.
+    QueueStatus { data: QueueData },
+    /// A chat line or an emote from a player.
+    Chat { data: ChatLine },
+    /// Recent chat lines, sent to a player who rejoins.
+    ChatHistory { data: Vec<ChatLine> },
+    /// The server removed the game, the socket is closed right after.
+    GameClosed { message: String },
+    /// The server is stopping, the socket is closed right after. When `resumable`, rejoin with the
+    /// session token once it is back, otherwise the game is lost.
+    ServerRestarting { message: String, resumable: bool },
+}
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Success,
+    Failed,
+}
+
+/// A `ServerMessage` as it goes over the wire, with its status.
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Serialize)]
+pub struct ServerEnvelope<'a> {
+    status: Status,
+    #[serde(flatten)]
+    message: &'a ServerMessage,
+}
+
+impl ServerMessage {
+    pub fn reply(request_id: Option<String>, error: Option<ErrorCode>) -> Self {
+        ServerMessage::Reply {
+            request_id,
+            error: error.map(|code| ErrorData { code, message: code.message().to_string() }),
+        }
+    }
+
+    pub fn encode(&self, encoding: Encoding) -> Message {
+        let status = match self {
+            ServerMessage::Reply { error: Some(_), .. } => Status::Failed,
+            _ => Status::Success,
+        };
+        let envelope = ServerEnvelope { status, message: self };
+        match encoding {
+            Encoding::Json => Message::Text(serde_json::to_string(&envelope).unwrap().into()),
+            Encoding::MessagePack => {
+                // Human readable keeps ids as strings, like in the JSON messages.
+                let mut buf = Vec::new();
+                let mut serializer = rmp_serde::Serializer::new(&mut buf).with_struct_map().with_human_readable();
+                envelope.serialize(&mut serializer).unwrap();
+                Message::Binary(buf.into())
+            }
+        }
+    }
+}
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionData {
+    pub player_id: Uuid,
+    pub token: String,
+    pub protocol_version: u8,
+}
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlayerInfoData {
+    pub players: Vec<PlayerData>,
+    pub spectators: usize,
+    pub rules: RuleSet,
+    /// Name of the host.
+    pub host: Option<String>,
+    pub locked: bool,
+    /// Names of the players ready to start, bots included.
+    pub ready: Vec<String>,
+    /// Unix timestamp in milliseconds at which the game starts on its own.
+    #[cfg_attr(test, ts(type = "number | null"))]
+    pub starts_at: Option<u64>,
+}
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GameData {
+    #[cfg_attr(test, ts(type = "number"))]
+    pub version: u64,
+    pub player_id: Uuid,
+    /// `None` for a viewer without a seat in the current round.
+    pub player_pos: Option<u8>,
+    pub num_of_players: u8,
+    pub card_left: u8,
+    pub current_turn: u8,
+    pub current_phase: GamePhase,
+    /// Unix timestamp in milliseconds at which the current turn is played automatically.
+    #[cfg_attr(test, ts(type = "number | null"))]
+    pub turn_deadline: Option<u64>,
+    pub rules: RuleSet,
+    pub event: GameEvent,
+    pub players: Vec<PlayerData>,
+}
+
+/// Changes from the game state at `base_version` to the one at `version`.
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct GameDeltaData {
+    #[cfg_attr(test, ts(type = "number"))]
+    pub base_version: u64,
+    #[cfg_attr(test, ts(type = "number"))]
+    pub version: u64,
+    pub card_left: u8,
+    pub current_turn: u8,
+    pub current_phase: GamePhase,
+    #[cfg_attr(test, ts(type = "number | null"))]
+    pub turn_deadline: Option<u64>,
+    pub event: GameEvent,
+    /// Only the seats whose hand or bin changed.
+    pub players: Vec<PlayerDelta>,
+}
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct PlayerDelta {
+    pub seat: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(test, ts(optional))]
+    pub hand: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(test, ts(optional))]
+    pub bin: Option<Vec<String>>,
+}
+
+impl GameDeltaData {
+    /// Changes from `base` to `data`, `None` when the seats changed and only a snapshot will do.
+    pub fn diff(base: &GameData, data: &GameData) -> Option<GameDeltaData> {
+        let same_seats = base.player_id == data.player_id
+            && base.player_pos == data.player_pos
+            && base.num_of_players == data.num_of_players
+            && base.rules == data.rules
+            && base.players.len() == data.players.len()
+            && base.players.iter().zip(&data.players).all(|(before, after)| before.name == after.name && before.is_bot == after.is_bot);
+        if !same_seats {
+            return None;
+        }
+
+        let players = base.players.iter().zip(&data.players).enumerate()
+            .filter_map(|(seat, (before, after))| {
+                let hand = (before.hand != after.hand).then(|| after.hand.clone());
+                let bin = (before.bin != after.bin).then(|| after.bin.clone());
+                (hand.is_some() || bin.is_some()).then_some(PlayerDelta { seat: seat as u8, hand, bin })
+            })
+            .collect();
+
+        Some(GameDeltaData {
+            base_version: base.version,
+            version: data.version,
+            card_left: data.card_left,
+            current_turn: data.current_turn,
+            current_phase: data.current_phase.clone(),
+            turn_deadline: data.turn_deadline,
+            event: data.event.clone(),
+            players,
+        })
+    }
+}
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlayerData {
+    pub name: String,
+    pub is_bot: bool,
+    pub hand: Vec<String>,
+    pub bin: Vec<String>,
+}
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GameEventType {
+    GameStart,
+    Draw,
+    TakeBin,
+    Discard,
+    Close,
+    Reconnect,
+    Watch,
+    Sync,
+}
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GameEvent {
+    pub event_type: GameEventType,
+    pub from: Option<u8>,
+    pub to: Option<u8>,
+}
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EndGameScores {
+    pub name: String,
+    pub score: i16,
+    pub hand: Vec<String>,
+}
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EndGameData {
+    pub winner_name: Option<String>,
+    pub players: Vec<EndGameScores>,
+}
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MatchScoreEntry {
+    pub name: String,
+    pub total: i32,
+    pub eliminated: bool,
+}
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MatchScoreData {
+    pub rounds_played: u8,
+    pub rounds: u8,
+    pub target_score: Option<i32>,
+    pub finished: bool,
+    pub winner_name: Option<String>,
+    pub players: Vec<MatchScoreEntry>,
+}
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RematchData {
+    pub votes: Vec<String>,
+    pub needed: usize,
+    pub accepted: bool,
+}
+
+/// A public game waiting in its lobby, as listed by `/lobbies` and the lobby feed.
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LobbySummary {
+    pub game_id: String,
+    pub players: usize,
+    pub max_players: usize,
+    pub password_protected: bool,
+    pub rounds: u8,
+    pub rules: RuleSet,
+    /// Unix timestamp in milliseconds.
+    #[cfg_attr(test, ts(type = "number"))]
+    pub created_at: u64,
+}
+
+/// Either a text line or an emote.
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatLine {
+    pub name: String,
+    pub text: Option<String>,
+    pub emote: Option<Emote>,
+    /// Unix timestamp in milliseconds.
+    #[cfg_attr(test, ts(type = "number"))]
+    pub sent_at: u64,
+}
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LobbyRemovedData {
+    pub game_id: String,
+}
+
+#[cfg_attr(test, derive(schemars::JsonSchema, ts_rs::TS))]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QueueData {
+    pub table_size: usize,
+    /// Players waiting for this table size, including the receiver.
+    pub waiting: usize,
+    /// Seconds after joining the queue at which the remaining seats are filled with bots.
+    #[cfg_attr(test, ts(type = "number"))]
+    pub timeout_secs: u64,
+}
@@ -1,4 +1,5 @@
-use crate::handlers::game::{create_game, game};
+use crate::handlers::game::{create_game, game, quick_match, rejoin, replay, watch};
+use crate::handlers::lobby::{list_lobbies, lobby_feed};
 use crate::state::state::GameManager;
 use axum::routing::get;
 use axum::Router;
@@ -9,7 +10,13 @@ use tower_http::cors::CorsLayer;
 pub fn create_router(state: Arc<RwLock<GameManager>>, cors_layer: CorsLayer) -> Router {
     Router::new()
         .route("/create", get(create_game))
+        .route("/lobbies", get(list_lobbies))
+        .route("/lobbies/feed", get(lobby_feed))
+        .route("/quickmatch", get(quick_match))
         .route("/{game_id}/join", get(game))
+        .route("/{game_id}/rejoin", get(rejoin))
+        .route("/{game_id}/watch", get(watch))
+        .route("/{game_id}/replay", get(replay))
         .with_state(state)
         .layer(cors_layer)
 
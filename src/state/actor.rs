@@ -0,0 +1,147 @@
+use crate::protocol::LobbySummary;
+use crate::state::chat::ChatFilter;
+use crate::state::cluster::ClusterNode;
+use crate::state::state::{GameState, LobbyFeed};
+use crate::state::storage::{GameRepository, StorageWriter};
+use crate::utils::lock;
+use chrono::Utc;
+use std::cell::Cell;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::oneshot;
+
+/// Work for a game's task, run with exclusive access to its state.
+type GameCommand = Box<dyn FnOnce(&mut GameState, &GameContext) + Send>;
+
+/// Server-wide dependencies shared by every game task.
+pub struct GameServices {
+    storage: StorageWriter,
+    lobby_feed: Mutex<LobbyFeed>,
+    /// Run on every chat line.
+    pub chat_filter: Box<dyn ChatFilter>,
+    pub cluster: Arc<ClusterNode>,
+}
+
+impl GameServices {
+    pub fn new(repository: Box<dyn GameRepository>, chat_filter: Box<dyn ChatFilter>, cluster: Arc<ClusterNode>) -> Self {
+        GameServices { storage: StorageWriter::new(repository), lobby_feed: Mutex::new(LobbyFeed::default()), chat_filter, cluster }
+    }
+
+    pub fn repository(&self) -> &dyn GameRepository {
+        self.storage.repository()
+    }
+
+    /// Where games are saved, off their tasks.
+    pub fn storage(&self) -> &StorageWriter {
+        &self.storage
+    }
+
+    /// A game panicking while publishing must not take the feed down for every other game.
+    pub fn lobby_feed(&self) -> MutexGuard<'_, LobbyFeed> {
+        lock(&self.lobby_feed)
+    }
+
+    /// Update a game's listing here and on every other node.
+    pub fn publish_lobby(&self, game_id: &str, summary: Option<LobbySummary>) {
+        self.lobby_feed().publish(game_id, summary.clone());
+        self.cluster.publish_lobby(game_id, summary);
+    }
+}
+
+/// Sends commands to the task owning a game. Cheap to clone, timers keep one to reach their game later.
+#[derive(Clone)]
+pub struct GameHandle {
+    pub id: String,
+    commands: UnboundedSender<GameCommand>,
+}
+
+impl GameHandle {
+    /// Run `command` on the game's task without waiting for it. Returns `false` once the game is gone.
+    pub fn send(&self, command: impl FnOnce(&mut GameState, &GameContext) + Send + 'static) -> bool {
+        self.commands.send(Box::new(command)).is_ok()
+    }
+
+    /// Run `command` on the game's task and wait for its result, `None` once the game is gone.
+    pub async fn call<R: Send + 'static>(&self, command: impl FnOnce(&mut GameState, &GameContext) -> R + Send + 'static) -> Option<R> {
+        let (tx, rx) = oneshot::channel();
+        self.send(move |game_state, ctx| {
+            let _ = tx.send(command(game_state, ctx));
+        });
+        rx.await.ok()
+    }
+
+    /// Whether the task stopped, after the game was removed or panicked.
+    pub fn is_closed(&self) -> bool {
+        self.commands.is_closed()
+    }
+}
+
+/// Given to every command next to the game state.
+pub struct GameContext {
+    handle: GameHandle,
+    services: Arc<GameServices>,
+    stopped: Cell<bool>,
+}
+
+impl GameContext {
+    pub fn handle(&self) -> &GameHandle {
+        &self.handle
+    }
+
+    pub fn services(&self) -> &GameServices {
+        &self.services
+    }
+
+    /// Persist the game after a change, and update its listing on the lobby feed.
+    pub fn save(&self, game_state: &mut GameState) {
+        game_state.last_updated = Utc::now();
+        self.services.storage.save(game_state);
+        self.services.publish_lobby(&game_state.id, game_state.lobby_summary());
+    }
+
+    /// Save the game as it is and stop its task after the current command, so no later request
+    /// changes it. It resumes from storage when the server starts again, until then no node
+    /// hosts or lists it.
+    pub fn stop(&self, game_state: &GameState) {
+        self.services.storage.save(game_state);
+        self.services.publish_lobby(&game_state.id, None);
+        self.services.cluster.release(&game_state.id);
+        self.stopped.set(true);
+    }
+
+    /// Delete the game from storage, the lobby feed and the cluster. Its task stops after the current command.
+    pub fn remove(&self, game_state: &GameState) {
+        self.services.storage.remove(&game_state.id);
+        self.services.publish_lobby(&game_state.id, None);
+        self.services.cluster.release(&game_state.id);
+        self.stopped.set(true);
+    }
+}
+
+/// Spawn the task owning `game_state` and return its handle. Commands run one at a time in the
+/// order they were sent, until one removes the game. A panicking command only ends this game's
+/// task, the last saved state stays in storage.
+pub fn spawn_game(game_state: GameState, services: Arc<GameServices>) -> GameHandle {
+    let (tx, mut rx) = mpsc::unbounded_channel::<GameCommand>();
+    let handle = GameHandle { id: game_state.id.clone(), commands: tx };
+    let ctx = GameContext { handle: handle.clone(), services: services.clone(), stopped: Cell::new(false) };
+
+    let game_id = game_state.id.clone();
+    let task = tokio::spawn(async move {
+        let mut game_state = game_state;
+        while let Some(command) = rx.recv().await {
+            command(&mut game_state, &ctx);
+            if ctx.stopped.get() {
+                break;
+            }
+        }
+    });
+    tokio::spawn(async move {
+        if let Err(e) = task.await {
+            eprintln!("Game {} stopped: {:?}", game_id, e);
+            services.publish_lobby(&game_id, None);
+            services.cluster.release(&game_id);
+        }
+    });
+    handle
+}
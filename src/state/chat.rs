@@ -0,0 +1,80 @@
+use crate::protocol::ChatLine;
+use std::collections::{HashMap, VecDeque};
+use uuid::Uuid;
+
+/// Longest chat line accepted, in characters.
+pub const MAX_CHAT_LENGTH: usize = 200;
+/// Chat lines kept for players who reconnect.
+pub const CHAT_HISTORY: usize = 50;
+/// Chat lines and emotes a player may send within `RATE_WINDOW_MS`.
+pub const RATE_LIMIT: usize = 5;
+pub const RATE_WINDOW_MS: u64 = 10_000;
+
+/// Hook run on every chat line before it is broadcast.
+pub trait ChatFilter: Send + Sync {
+    /// The text to broadcast, or `None` to reject the line.
+    fn filter(&self, text: &str) -> Option<String>;
+}
+
+/// Masks blocked words with asterisks, whatever their case. Without words every line passes unchanged.
+#[derive(Default)]
+pub struct WordFilter {
+    words: Vec<String>,
+}
+
+impl WordFilter {
+    pub fn new(words: &[String]) -> Self {
+        WordFilter { words: words.iter().map(|word| word.to_lowercase()).filter(|word| !word.is_empty()).collect() }
+    }
+}
+
+impl ChatFilter for WordFilter {
+    fn filter(&self, text: &str) -> Option<String> {
+        let masked = text.split(' ').map(|word| {
+            let bare = word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase();
+            if self.words.contains(&bare) {
+                word.chars().map(|c| if c.is_alphanumeric() { '*' } else { c }).collect()
+            } else {
+                word.to_string()
+            }
+        });
+        Some(masked.collect::<Vec<String>>().join(" "))
+    }
+}
+
+/// Recent chat of a game and what each player sent lately, only kept in memory.
+#[derive(Clone, Default)]
+pub struct ChatLog {
+    lines: VecDeque<ChatLine>,
+    sent: HashMap<Uuid, VecDeque<u64>>,
+}
+
+impl ChatLog {
+    /// Count a message from `player_id` at `now`, unless they already sent `RATE_LIMIT` within the window.
+    pub fn allow(&mut self, player_id: Uuid, now: u64) -> bool {
+        let sent = self.sent.entry(player_id).or_default();
+        while sent.front().is_some_and(|at| at + RATE_WINDOW_MS <= now) {
+            sent.pop_front();
+        }
+        if sent.len() >= RATE_LIMIT {
+            return false;
+        }
+        sent.push_back(now);
+        true
+    }
+
+    pub fn push(&mut self, line: ChatLine) {
+        if self.lines.len() >= CHAT_HISTORY {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+
+    pub fn history(&self) -> Vec<ChatLine> {
+        self.lines.iter().cloned().collect()
+    }
+
+    pub fn forget(&mut self, player_id: &Uuid) {
+        self.sent.remove(player_id);
+    }
+}
@@ -0,0 +1,262 @@
+use crate::protocol::LobbySummary;
+use crate::utils::lock;
+use axum::extract::ws::{CloseFrame, Message};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+/// Channel every node listens to for lobby listings of the other nodes.
+const LOBBIES_CHANNEL: &str = "lobbies";
+
+/// Registry of which node hosts each game, and pub/sub between nodes. Implement it over an
+/// external store, e.g. a Redis-compatible one, to run nodes as separate processes.
+pub trait ClusterBackend: Send + Sync {
+    /// Record `node_id` as the host of `game_id` unless another node already is, and return the host.
+    fn claim(&self, game_id: &str, node_id: &str) -> String;
+    fn host(&self, game_id: &str) -> Option<String>;
+    /// Forget the host of `game_id`, if it still is `node_id`.
+    fn release(&self, game_id: &str, node_id: &str);
+    fn publish(&self, channel: &str, payload: Vec<u8>);
+    fn subscribe(&self, channel: &str) -> UnboundedReceiver<Vec<u8>>;
+}
+
+/// Backend kept in process memory, for nodes running in the same process.
+#[derive(Default)]
+pub struct LocalCluster {
+    hosts: Mutex<HashMap<String, String>>,
+    subscribers: Mutex<HashMap<String, Vec<UnboundedSender<Vec<u8>>>>>,
+}
+
+impl ClusterBackend for LocalCluster {
+    fn claim(&self, game_id: &str, node_id: &str) -> String {
+        lock(&self.hosts).entry(game_id.to_string()).or_insert_with(|| node_id.to_string()).clone()
+    }
+
+    fn host(&self, game_id: &str) -> Option<String> {
+        lock(&self.hosts).get(game_id).cloned()
+    }
+
+    fn release(&self, game_id: &str, node_id: &str) {
+        let mut hosts = lock(&self.hosts);
+        if hosts.get(game_id).is_some_and(|host| host == node_id) {
+            hosts.remove(game_id);
+        }
+    }
+
+    fn publish(&self, channel: &str, payload: Vec<u8>) {
+        if let Some(subscribers) = lock(&self.subscribers).get_mut(channel) {
+            subscribers.retain(|subscriber| subscriber.send(payload.clone()).is_ok());
+        }
+    }
+
+    fn subscribe(&self, channel: &str) -> UnboundedReceiver<Vec<u8>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        lock(&self.subscribers).entry(channel.to_string()).or_default().push(tx);
+        rx
+    }
+}
+
+/// What a client socket relayed to the host of its game asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionKind {
+    Join,
+    Rejoin,
+    Watch,
+}
+
+/// A websocket frame of a relayed connection.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelayedFrame {
+    Text(String),
+    Binary(Vec<u8>),
+    Close { code: u16, reason: String },
+}
+
+impl RelayedFrame {
+    /// Pings and pongs are answered by each socket's own node and never relayed.
+    pub fn from_message(message: Message) -> Option<RelayedFrame> {
+        match message {
+            Message::Text(text) => Some(RelayedFrame::Text(text.to_string())),
+            Message::Binary(bytes) => Some(RelayedFrame::Binary(bytes.to_vec())),
+            Message::Close(frame) => Some(frame.map_or(RelayedFrame::Close { code: 1000, reason: String::new() }, |frame| {
+                RelayedFrame::Close { code: frame.code, reason: frame.reason.to_string() }
+            })),
+            Message::Ping(_) | Message::Pong(_) => None,
+        }
+    }
+
+    pub fn into_message(self) -> Message {
+        match self {
+            RelayedFrame::Text(text) => Message::Text(text.into()),
+            RelayedFrame::Binary(bytes) => Message::Binary(bytes.into()),
+            RelayedFrame::Close { code, reason } => Message::Close(Some(CloseFrame { code, reason: reason.into() })),
+        }
+    }
+}
+
+/// HTTP status and message of a socket the host refused.
+pub type Refusal = (u16, String);
+
+/// Sent between nodes, on the channel of the receiving node unless stated otherwise.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClusterMessage {
+    /// Ask the host of `game_id` to accept a socket held by `node_id`.
+    Open { connection: Uuid, node_id: String, kind: ConnectionKind, game_id: String, params: HashMap<String, String> },
+    /// The host's answer to `Open`, with the HTTP status and message when it refused the socket.
+    Opened { connection: Uuid, error: Option<Refusal> },
+    /// The client's socket was upgraded after `Opened`, the host may start serving it.
+    Ready { connection: Uuid },
+    /// A frame of a relayed connection, from the client to the host or back.
+    Frame { connection: Uuid, frame: RelayedFrame },
+    /// Either end of a relayed connection went away.
+    Closed { connection: Uuid },
+    /// A game's listing changed on its host, published to every node. Also sent to a node that
+    /// just started, once for each game listed on the sender.
+    Lobby { node_id: String, game_id: String, summary: Option<LobbySummary> },
+    /// A node started listening, published to every node so they send it their listings.
+    Hello { node_id: String },
+    /// Ask the host of `game_id` for the replay export matching the `query` string.
+    Replay { request: Uuid, node_id: String, game_id: String, query: String },
+    /// The host's answer to `Replay`, the JSON body or the HTTP status and message of the error.
+    Replayed { request: Uuid, result: Result<serde_json::Value, Refusal> },
+}
+
+impl ClusterMessage {
+    pub fn decode(payload: &[u8]) -> Option<ClusterMessage> {
+        serde_json::from_slice(payload).map_err(|e| eprintln!("Error decoding cluster message: {:?}", e)).ok()
+    }
+}
+
+/// This server's membership in the cluster, and the relayed connections with an end on it.
+pub struct ClusterNode {
+    pub id: String,
+    backend: Arc<dyn ClusterBackend>,
+    /// Where frames of each relayed connection are delivered on this node.
+    relays: Mutex<HashMap<Uuid, UnboundedSender<Message>>>,
+    /// Relayed connections waiting for the host to accept them.
+    pending: Mutex<HashMap<Uuid, oneshot::Sender<Option<Refusal>>>>,
+    /// Relayed connections accepted here, waiting for the other node to upgrade the client's socket.
+    upgrading: Mutex<HashMap<Uuid, oneshot::Sender<()>>>,
+    /// Replay exports waiting for the host's answer.
+    replays: Mutex<HashMap<Uuid, oneshot::Sender<Result<serde_json::Value, Refusal>>>>,
+}
+
+impl ClusterNode {
+    pub fn new(id: String, backend: Arc<dyn ClusterBackend>) -> Self {
+        ClusterNode { id, backend, relays: Mutex::default(), pending: Mutex::default(), upgrading: Mutex::default(), replays: Mutex::default() }
+    }
+
+    /// Messages sent to this node, and the lobby listings of every node. The other nodes are asked
+    /// for the listings they published before this one was listening.
+    pub fn subscribe(&self) -> (UnboundedReceiver<Vec<u8>>, UnboundedReceiver<Vec<u8>>) {
+        let channels = (self.backend.subscribe(&node_channel(&self.id)), self.backend.subscribe(LOBBIES_CHANNEL));
+        self.publish(&ClusterMessage::Hello { node_id: self.id.clone() });
+        channels
+    }
+
+    /// Host the game here, unless another node already does, in which case its id is the error.
+    pub fn claim(&self, game_id: &str) -> Result<(), String> {
+        let host = self.backend.claim(game_id, &self.id);
+        if host == self.id {
+            Ok(())
+        } else {
+            Err(host)
+        }
+    }
+
+    pub fn host(&self, game_id: &str) -> Option<String> {
+        self.backend.host(game_id)
+    }
+
+    pub fn release(&self, game_id: &str) {
+        self.backend.release(game_id, &self.id);
+    }
+
+    pub fn send(&self, node_id: &str, message: &ClusterMessage) {
+        match serde_json::to_vec(message) {
+            Ok(payload) => self.backend.publish(&node_channel(node_id), payload),
+            Err(e) => eprintln!("Error encoding cluster message: {:?}", e),
+        }
+    }
+
+    /// Tell the other nodes about a change to a game's listing.
+    pub fn publish_lobby(&self, game_id: &str, summary: Option<LobbySummary>) {
+        self.publish(&ClusterMessage::Lobby { node_id: self.id.clone(), game_id: game_id.to_string(), summary });
+    }
+
+    fn publish(&self, message: &ClusterMessage) {
+        match serde_json::to_vec(message) {
+            Ok(payload) => self.backend.publish(LOBBIES_CHANNEL, payload),
+            Err(e) => eprintln!("Error encoding cluster message: {:?}", e),
+        }
+    }
+
+    /// Deliver the frames of `connection` to `tx` from now on.
+    pub fn relay(&self, connection: Uuid, tx: UnboundedSender<Message>) {
+        lock(&self.relays).insert(connection, tx);
+    }
+
+    pub fn deliver(&self, connection: Uuid, message: Message) {
+        if let Some(tx) = lock(&self.relays).get(&connection) {
+            let _ = tx.send(message);
+        }
+    }
+
+    /// Drop whatever this node keeps for a relayed connection or a replay request.
+    pub fn forget(&self, id: Uuid) {
+        lock(&self.relays).remove(&id);
+        lock(&self.pending).remove(&id);
+        lock(&self.upgrading).remove(&id);
+        lock(&self.replays).remove(&id);
+    }
+
+    /// Resolves with the host's answer to the `Open` of `connection`.
+    pub fn await_opened(&self, connection: Uuid) -> oneshot::Receiver<Option<Refusal>> {
+        let (tx, rx) = oneshot::channel();
+        lock(&self.pending).insert(connection, tx);
+        rx
+    }
+
+    pub fn opened(&self, connection: Uuid, error: Option<Refusal>) {
+        if let Some(tx) = lock(&self.pending).remove(&connection) {
+            let _ = tx.send(error);
+        }
+    }
+
+    /// Resolves once the other node upgraded the client's socket of `connection`, and fails if
+    /// the connection is forgotten first.
+    pub fn await_ready(&self, connection: Uuid) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        lock(&self.upgrading).insert(connection, tx);
+        rx
+    }
+
+    pub fn ready(&self, connection: Uuid) {
+        if let Some(tx) = lock(&self.upgrading).remove(&connection) {
+            let _ = tx.send(());
+        }
+    }
+
+    /// Resolves with the host's answer to the `Replay` of `request`.
+    pub fn await_replay(&self, request: Uuid) -> oneshot::Receiver<Result<serde_json::Value, Refusal>> {
+        let (tx, rx) = oneshot::channel();
+        lock(&self.replays).insert(request, tx);
+        rx
+    }
+
+    pub fn replayed(&self, request: Uuid, result: Result<serde_json::Value, Refusal>) {
+        if let Some(tx) = lock(&self.replays).remove(&request) {
+            let _ = tx.send(result);
+        }
+    }
+}
+
+fn node_channel(node_id: &str) -> String {
+    format!("node:{}", node_id)
+}
@@ -0,0 +1,69 @@
+use crate::protocol::{ServerMessage, SocketOptions};
+use axum::extract::ws::Message;
+use std::collections::HashMap;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+/// A player waiting for a quick match, with the socket they will play on.
+pub struct QueuedPlayer {
+    pub player_id: Uuid,
+    pub name: String,
+    pub connection_id: Uuid,
+    pub sender: UnboundedSender<Message>,
+    pub options: SocketOptions,
+    /// Told the id of the game once the player is seated.
+    pub matched: oneshot::Sender<String>,
+}
+
+impl QueuedPlayer {
+    pub fn send_message(&self, message: &ServerMessage) {
+        if let Err(e) = self.sender.send(message.encode(self.options.encoding)) {
+            eprintln!("Error sending message: {:?}", e.to_string());
+        }
+    }
+}
+
+/// Players waiting for a quick match, in arrival order per table size.
+#[derive(Default)]
+pub struct Matchmaker {
+    queues: HashMap<usize, Vec<QueuedPlayer>>,
+}
+
+impl Matchmaker {
+    /// Queue a player, and return the players of a full table once they complete one.
+    pub fn join(&mut self, table_size: usize, player: QueuedPlayer) -> Option<Vec<QueuedPlayer>> {
+        let queue = self.queues.entry(table_size).or_default();
+        queue.push(player);
+        (queue.len() >= table_size).then(|| queue.drain(..table_size).collect())
+    }
+
+    /// Take everyone waiting for `table_size` as long as `player_id` is still among them.
+    pub fn take_waiting(&mut self, table_size: usize, player_id: &Uuid) -> Option<Vec<QueuedPlayer>> {
+        let queue = self.queues.get_mut(&table_size)?;
+        if !queue.iter().any(|player| player.player_id == *player_id) {
+            return None;
+        }
+        let count = queue.len().min(table_size);
+        Some(queue.drain(..count).collect())
+    }
+
+    /// Drop a player who gave up waiting, returns the table size they waited for.
+    pub fn leave(&mut self, player_id: &Uuid) -> Option<usize> {
+        for (table_size, queue) in self.queues.iter_mut() {
+            if let Some(pos) = queue.iter().position(|player| player.player_id == *player_id) {
+                queue.remove(pos);
+                return Some(*table_size);
+            }
+        }
+        None
+    }
+
+    pub fn queued(&self) -> impl Iterator<Item = &QueuedPlayer> {
+        self.queues.values().flatten()
+    }
+
+    pub fn waiting(&self, table_size: usize) -> &[QueuedPlayer] {
+        self.queues.get(&table_size).map_or(&[], Vec::as_slice)
+    }
+}
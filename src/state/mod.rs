@@ -1 +1,9 @@
-pub mod state;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod state;
+pub mod storage;
+pub mod matchmaking;
+pub mod chat;
+pub mod actor;
+pub mod cluster;
+
+mod test;
\ No newline at end of file
@@ -1,45 +1,489 @@
-use crate::engine::game::Game;
-use crate::utils::generate_short_uuid;
-use serde::Serialize;
-use std::collections::HashMap;
+use crate::config::Config;
+use crate::engine::bot::BotDifficulty;
+use crate::engine::game::{Game, GamePhase};
+use crate::engine::game_match::{GameMatch, MatchSettings};
+use crate::engine::replay::GameLog;
+use crate::engine::rules::RuleSet;
+use crate::protocol::{Encoding, GameData, GameDeltaData, LobbyRemovedData, LobbySummary, ServerMessage, UpdateMode};
+use crate::state::actor::{spawn_game, GameHandle, GameServices};
+use crate::state::chat::{ChatLog, WordFilter};
+use crate::state::cluster::ClusterNode;
+use crate::state::matchmaking::Matchmaker;
+use crate::state::storage::GameRepository;
+use crate::utils::{constant_time_eq, generate_short_uuid, hash_password, unix_millis, verify_password};
+use axum::extract::ws::Message;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
 use uuid::Uuid;
 
-#[derive(Clone, Debug, Serialize, PartialEq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub enum GameStateStatus {
     Lobby,
     InProgress,
     Finished,
 }
-#[derive(Clone)]
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PlayerConnection {
+    pub name: String,
+    /// `None` while the player is disconnected and their seat is held for the grace period.
+    #[serde(skip)]
+    pub sender: Option<UnboundedSender<Message>>,
+    /// Identifies the socket currently attached to the seat, so a stale socket closing
+    /// after a rejoin does not vacate the seat.
+    pub connection_id: Uuid,
+    /// Seat played by the server, it never has a socket.
+    #[serde(default)]
+    pub bot: Option<BotDifficulty>,
+    /// Encoding picked by the socket at join.
+    #[serde(skip)]
+    pub encoding: Encoding,
+    /// Whether the socket gets full snapshots or deltas of the game state.
+    #[serde(skip)]
+    pub updates: UpdateMode,
+    #[serde(skip)]
+    pub views: ViewHistory,
+}
+
+impl PlayerConnection {
+    pub fn send(&self, message: Message) {
+        if let Some(sender) = &self.sender {
+            if let Err(e) = sender.send(message) {
+                eprintln!("Error sending message: {:?}", e.to_string());
+            }
+        }
+    }
+
+    /// Send a message in the encoding this connection asked for.
+    pub fn send_message(&self, message: &ServerMessage) {
+        self.send(message.encode(self.encoding));
+    }
+}
+
+/// Views of the game sent to a delta socket, kept from its last acknowledged one until the
+/// client acknowledges a newer one.
+#[derive(Clone, Debug, Default)]
+pub struct ViewHistory {
+    acked: Option<GameData>,
+    sent: Vec<GameData>,
+}
+
+/// Past this many views without an acknowledgement, deltas give way to snapshots.
+const MAX_UNACKED_VIEWS: usize = 32;
+
+/// How many spectators may watch one game at a time.
+pub const MAX_SPECTATORS: usize = 50;
+
+impl ViewHistory {
+    pub fn snapshot(&mut self, data: GameData) -> ServerMessage {
+        if self.sent.len() >= MAX_UNACKED_VIEWS {
+            self.sent.remove(0);
+        }
+        self.sent.push(data.clone());
+        ServerMessage::GameEvent { data }
+    }
+
+    /// The changes since the acknowledged view, or a snapshot when there is none to diff against.
+    pub fn update(&mut self, data: GameData) -> ServerMessage {
+        let delta = match &self.acked {
+            Some(base) if self.sent.len() < MAX_UNACKED_VIEWS => GameDeltaData::diff(base, &data),
+            _ => None,
+        };
+        match delta {
+            Some(delta) => {
+                self.sent.push(data);
+                ServerMessage::GameDelta { data: delta }
+            }
+            None => self.snapshot(data),
+        }
+    }
+
+    /// Base later deltas on the view at `version`. `false` when no such view was sent.
+    pub fn ack(&mut self, version: u64) -> bool {
+        match self.sent.iter().rposition(|view| view.version == version) {
+            Some(pos) => {
+                self.acked = self.sent.drain(..=pos).next_back();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Who may join a lobby.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LobbyAccess {
+    /// Argon2 hash of the join password, see `hash_password`.
+    pub password_hash: Option<String>,
+    /// Code needed to join a private lobby, which is also left out of listings.
+    pub invite_code: Option<String>,
+}
+
+impl LobbyAccess {
+    /// Hashes the password, which blocks for a while.
+    pub fn new(password: Option<&str>, private: bool) -> Self {
+        LobbyAccess {
+            password_hash: password.map(hash_password),
+            invite_code: private.then(generate_short_uuid),
+        }
+    }
+
+    /// Blocks for as long as hashing the password does.
+    pub fn check_password(&self, password: Option<&String>) -> bool {
+        match &self.password_hash {
+            Some(hash) => password.is_some_and(|password| verify_password(hash, password)),
+            None => true,
+        }
+    }
+
+    pub fn check_invite(&self, invite: Option<&String>) -> bool {
+        match &self.invite_code {
+            Some(code) => invite.is_some_and(|invite| constant_time_eq(invite, code)),
+            None => true,
+        }
+    }
+}
+
+/// When the current player's turn times out.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TurnDeadline {
+    pub player_id: Uuid,
+    /// Unix timestamp in milliseconds.
+    pub expires_at: u64,
+    /// Whether a timer task is already waiting on this deadline.
+    #[serde(skip)]
+    pub scheduled: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct GameState {
     pub id: String,
     // pub num_player: u8,
     pub status: GameStateStatus,
+    #[serde(default)]
+    pub rules: RuleSet,
+    #[serde(default)]
+    pub match_settings: MatchSettings,
+    #[serde(default)]
+    pub access: LobbyAccess,
+    /// Player who starts the game and administers the lobby.
+    #[serde(default)]
+    pub host: Option<Uuid>,
+    /// A locked lobby lets no new player join.
+    #[serde(default)]
+    pub locked: bool,
+    /// Running totals across rounds, created when the first round starts.
+    #[serde(default)]
+    pub game_match: Option<GameMatch>,
     pub game: Option<Game>,
-    // pub date_created: DateTime<Utc>,
-    // pub last_updated: DateTime<Utc>,
-    pub players: HashMap<Uuid, (String,tokio::sync::mpsc::UnboundedSender<axum::extract::ws::Message>)>,
+    /// Logs of the rounds played so far, for the replay export.
+    #[serde(default)]
+    pub round_logs: Vec<GameLog>,
+    /// Round logs of the matches finished before a rematch, oldest first.
+    #[serde(default)]
+    pub past_matches: Vec<Vec<GameLog>>,
+    /// Players who asked for a rematch after the game finished.
+    #[serde(default)]
+    pub rematch_votes: HashSet<Uuid>,
+    /// Players in the lobby who are ready to start. Bots are always ready.
+    #[serde(default)]
+    pub ready: HashSet<Uuid>,
+    /// Unix timestamp in milliseconds at which the lobby starts on its own, while everyone is ready.
+    #[serde(skip)]
+    pub starts_at: Option<u64>,
+    /// Deadline of the current turn when the rules set a turn timeout.
+    #[serde(default)]
+    pub turn_deadline: Option<TurnDeadline>,
+    /// Consecutive timed out turns per player.
+    #[serde(default)]
+    pub timeouts: HashMap<Uuid, u8>,
+    #[serde(default = "Utc::now")]
+    pub date_created: DateTime<Utc>,
+    /// Last change to the game, expired games are removed based on it.
+    #[serde(default = "Utc::now")]
+    pub last_updated: DateTime<Utc>,
+    pub players: HashMap<Uuid, PlayerConnection>,
+    #[serde(skip)]
+    pub chat: ChatLog,
+    /// Non-seated observers, they only receive broadcasts and are not persisted.
+    #[serde(skip)]
+    pub spectators: HashMap<Uuid, PlayerConnection>,
+}
+
+impl GameState {
+    /// Listing of the game while it is a public lobby with a free seat.
+    pub fn lobby_summary(&self) -> Option<LobbySummary> {
+        let listed = self.status == GameStateStatus::Lobby
+            && !self.locked
+            && self.access.invite_code.is_none()
+            && self.players.len() < self.rules.max_players;
+        listed.then(|| LobbySummary {
+            game_id: self.id.clone(),
+            players: self.players.len(),
+            max_players: self.rules.max_players,
+            password_protected: self.access.password_hash.is_some(),
+            rounds: self.match_settings.rounds,
+            rules: self.rules.clone(),
+            created_at: self.date_created.timestamp_millis() as u64,
+        })
+    }
+
+    /// Players with a socket attached, bots and disconnected seats excluded.
+    pub fn connected_players(&self) -> impl Iterator<Item = (&Uuid, &PlayerConnection)> {
+        self.players.iter().filter(|(_, player)| player.sender.is_some())
+    }
+
+    /// Hand the host role to a connected player when the host has lost their socket or seat,
+    /// and return the new host. The host is kept while nobody else is connected.
+    pub fn migrate_host(&mut self) -> Option<Uuid> {
+        let host_connected = self.host.is_some_and(|host| {
+            self.players.get(&host).is_some_and(|player| player.sender.is_some())
+        });
+        if host_connected {
+            return None;
+        }
+        let new_host = self.connected_players()
+            .filter(|(_, player)| player.bot.is_none())
+            .min_by(|(_, a), (_, b)| a.name.cmp(&b.name))
+            .map(|(id, _)| *id)?;
+        self.host = Some(new_host);
+        Some(new_host)
+    }
+
+    pub fn all_ready(&self) -> bool {
+        self.players.iter().all(|(id, player)| player.bot.is_some() || self.ready.contains(id))
+    }
+
+    /// Arm the auto-start countdown once every seated player of the lobby is ready, and disarm it
+    /// otherwise. Returns whether the countdown changed.
+    pub fn update_start_countdown(&mut self) -> bool {
+        let starts_at = match self.rules.start_countdown_secs {
+            Some(secs) if self.status == GameStateStatus::Lobby && self.players.len() >= 2 && self.all_ready() => {
+                self.starts_at.or_else(|| Some(unix_millis() + secs * 1000))
+            }
+            _ => None,
+        };
+        let changed = starts_at != self.starts_at;
+        self.starts_at = starts_at;
+        changed
+    }
+
+    /// Why the game should be removed at `now`, if it stayed untouched for longer than its TTL.
+    pub fn expired(&self, now: DateTime<Utc>, expiry: &GameExpiry) -> Option<&'static str> {
+        let idle = (now - self.last_updated).to_std().unwrap_or_default();
+        if self.status == GameStateStatus::Finished && idle >= expiry.finished {
+            Some("The game is over")
+        } else if self.connected_players().next().is_none() && idle >= expiry.abandoned {
+            Some("Every player left")
+        } else if self.status == GameStateStatus::Lobby && idle >= expiry.idle_lobby {
+            Some("The lobby was idle for too long")
+        } else {
+            None
+        }
+    }
+
+    /// Go back to the lobby with the same players, connections and id.
+    pub fn reset_to_lobby(&mut self) {
+        self.status = GameStateStatus::Lobby;
+        self.game = None;
+        self.game_match = None;
+        // Kept for the replay export of earlier matches.
+        if !self.round_logs.is_empty() {
+            self.past_matches.push(std::mem::take(&mut self.round_logs));
+        }
+        // The earlier match's deals are in its replay, a rematch from the same seed would repeat them.
+        self.match_settings.seed = None;
+        self.rematch_votes.clear();
+        self.ready.clear();
+        self.starts_at = None;
+        self.turn_deadline = None;
+        self.timeouts.clear();
+        for player in self.players.values_mut() {
+            player.views = ViewHistory::default();
+        }
+    }
+
+    /// Start a new deadline when the turn moved to another human player, and drop it when
+    /// no one is waited on.
+    pub fn update_turn_deadline(&mut self) {
+        let current = match (&self.game, self.rules.turn_timeout_secs) {
+            (Some(game), Some(secs)) if self.status == GameStateStatus::InProgress && game.phase != GamePhase::GameEnded => {
+                game.players.get(game.current_turn)
+                    .filter(|player| self.players.get(&player.id).is_some_and(|player| player.bot.is_none()))
+                    .map(|player| (player.id, secs))
+            }
+            _ => None,
+        };
+
+        match current {
+            Some((player_id, _)) if self.turn_deadline.as_ref().is_some_and(|deadline| deadline.player_id == player_id) => {}
+            Some((player_id, secs)) => {
+                self.turn_deadline = Some(TurnDeadline {
+                    player_id,
+                    expires_at: unix_millis() + secs * 1000,
+                    scheduled: false,
+                });
+            }
+            None => self.turn_deadline = None,
+        }
+    }
+}
+
+/// How long games may stay untouched before they are removed.
+#[derive(Clone, Copy, Debug)]
+pub struct GameExpiry {
+    pub interval: Duration,
+    pub idle_lobby: Duration,
+    /// Games without a connected player.
+    pub abandoned: Duration,
+    pub finished: Duration,
+}
+
+impl GameExpiry {
+    pub fn from_config(config: &Config) -> Self {
+        GameExpiry {
+            interval: Duration::from_secs(config.gc_interval_secs),
+            idle_lobby: Duration::from_secs(config.idle_lobby_ttl_secs),
+            abandoned: Duration::from_secs(config.abandoned_game_ttl_secs),
+            finished: Duration::from_secs(config.finished_game_ttl_secs),
+        }
+    }
+}
+
+/// Sockets watching the lobby list, and the listing each of them last saw per game.
+#[derive(Default)]
+pub struct LobbyFeed {
+    pub watchers: HashMap<Uuid, UnboundedSender<Message>>,
+    published: HashMap<String, LobbySummary>,
+}
+
+impl LobbyFeed {
+    /// Push the listing of `game_id` to every watcher when it changed since the last push.
+    pub fn publish(&mut self, game_id: &str, summary: Option<LobbySummary>) {
+        let message = match summary {
+            Some(summary) if self.published.get(game_id) != Some(&summary) => {
+                self.published.insert(game_id.to_string(), summary.clone());
+                ServerMessage::LobbyUpdate { data: summary }
+            }
+            None if self.published.remove(game_id).is_some() => {
+                ServerMessage::LobbyRemoved { data: LobbyRemovedData { game_id: game_id.to_string() } }
+            }
+            _ => return,
+        };
+        let message = message.encode(Encoding::Json);
+        self.watchers.retain(|_, sender| sender.send(message.clone()).is_ok());
+    }
+
+    /// Listing of a public lobby with a free seat.
+    pub fn summary(&self, game_id: &str) -> Option<&LobbySummary> {
+        self.published.get(game_id)
+    }
+
+    /// Public lobbies with a free seat, newest first.
+    pub fn lobbies(&self) -> Vec<LobbySummary> {
+        let mut lobbies: Vec<LobbySummary> = self.published.values().cloned().collect();
+        lobbies.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| a.game_id.cmp(&b.game_id)));
+        lobbies
+    }
 }
 
 pub struct GameManager {
-    pub games: HashMap<String, GameState>,
+    /// Handles to the task owning each game.
+    pub games: HashMap<String, GameHandle>,
+    pub services: Arc<GameServices>,
+    pub session_secret: String,
+    /// Whether session tokens stay valid after a restart.
+    pub fixed_session_secret: bool,
+    pub admin_token: Option<String>,
+    pub reconnect_grace: Duration,
+    pub matchmaker: Matchmaker,
+    pub matchmaking_timeout: Duration,
+    pub expiry: GameExpiry,
+    /// Set once the server is stopping, no game can be created or joined any more.
+    pub shutting_down: bool,
 }
 
 impl GameManager {
-    pub fn new() -> Self {
+    /// Build the manager and resume every game the repository still holds, with all seats disconnected.
+    pub fn new(config: &Config, repository: Box<dyn GameRepository>, cluster: Arc<ClusterNode>) -> Self {
+        let games = repository.load_all().unwrap_or_else(|e| {
+            eprintln!("Error loading stored games: {:?}", e);
+            vec![]
+        });
+        let services = Arc::new(GameServices::new(repository, Box::new(WordFilter::new(&config.chat_blocked_words)), cluster));
+        let games = games.into_iter().filter_map(|game_state| {
+            // Running a game another node hosts would split it in two.
+            if let Err(host) = services.cluster.claim(&game_state.id) {
+                eprintln!("Not resuming game {}, it is hosted by node {}", game_state.id, host);
+                return None;
+            }
+            services.publish_lobby(&game_state.id, game_state.lobby_summary());
+            Some((game_state.id.clone(), spawn_game(game_state, services.clone())))
+        }).collect();
+
         Self {
-            games: HashMap::new(),
+            games,
+            services,
+            session_secret: config.session_secret.clone(),
+            fixed_session_secret: config.fixed_session_secret,
+            admin_token: config.admin_token.clone(),
+            reconnect_grace: Duration::from_secs(config.reconnect_grace_secs),
+            matchmaker: Matchmaker::default(),
+            matchmaking_timeout: Duration::from_secs(config.matchmaking_timeout_secs),
+            expiry: GameExpiry::from_config(config),
+            shutting_down: false,
         }
     }
 
-    pub fn create_game(&mut self) -> GameState {
+    /// Whether players can rejoin their games after a restart: the games are stored and their
+    /// session tokens still verify.
+    pub fn resumable(&self) -> bool {
+        self.services.repository().persistent() && self.fixed_session_secret
+    }
+
+    /// Handle of a game whose task is still running.
+    pub fn game(&self, game_id: &str) -> Option<GameHandle> {
+        self.games.get(game_id).filter(|handle| !handle.is_closed()).cloned()
+    }
+
+    pub fn create_game(&mut self, rules: RuleSet, match_settings: MatchSettings, access: LobbyAccess) -> GameState {
+        let id = loop {
+            let id = generate_short_uuid();
+            match self.services.cluster.claim(&id) {
+                Ok(()) => break id,
+                Err(host) => eprintln!("Game id {} is already taken by node {}", id, host),
+            }
+        };
         let game = GameState {
-            id: generate_short_uuid(),
+            access,
+            date_created: Utc::now(),
+            last_updated: Utc::now(),
+            host: None,
+            locked: false,
+            id,
             status: GameStateStatus::Lobby,
+            rules,
+            match_settings,
+            game_match: None,
             game: None,
+            round_logs: vec![],
+            past_matches: vec![],
+            rematch_votes: HashSet::new(),
+            ready: HashSet::new(),
+            starts_at: None,
+            chat: ChatLog::default(),
+            turn_deadline: None,
+            timeouts: HashMap::new(),
             players: HashMap::new(),
+            spectators: HashMap::new(),
         };
-        self.games.insert(game.id.clone(), game.clone());
+        self.services.storage().save(&game);
+        self.services.publish_lobby(&game.id, game.lobby_summary());
+        self.games.insert(game.id.clone(), spawn_game(game.clone(), self.services.clone()));
         game
     }
 }
\ No newline at end of file
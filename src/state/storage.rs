@@ -0,0 +1,207 @@
+use crate::state::state::GameState;
+use crate::utils::lock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::oneshot;
+
+/// Where `GameManager` keeps games so they outlive the process.
+pub trait GameRepository: Send + Sync {
+    fn save(&self, game_state: &GameState) -> io::Result<()>;
+    fn remove(&self, game_id: &str) -> io::Result<()>;
+    fn load_all(&self) -> io::Result<Vec<GameState>>;
+    /// Whether saved games outlive the server process.
+    fn persistent(&self) -> bool {
+        true
+    }
+}
+
+/// Keeps games in a map, nothing survives a restart.
+#[derive(Default)]
+pub struct InMemoryRepository {
+    games: Mutex<HashMap<String, GameState>>,
+}
+
+impl GameRepository for InMemoryRepository {
+    fn save(&self, game_state: &GameState) -> io::Result<()> {
+        lock(&self.games).insert(game_state.id.clone(), game_state.clone());
+        Ok(())
+    }
+
+    fn remove(&self, game_id: &str) -> io::Result<()> {
+        lock(&self.games).remove(game_id);
+        Ok(())
+    }
+
+    fn load_all(&self) -> io::Result<Vec<GameState>> {
+        Ok(lock(&self.games).values().cloned().collect())
+    }
+
+    fn persistent(&self) -> bool {
+        false
+    }
+}
+
+enum StorageWrite {
+    Save(Box<GameState>),
+    Remove(String),
+    /// Answered once every write queued before it is done.
+    Flush(oneshot::Sender<()>),
+}
+
+/// Runs the writes to a repository on a thread of its own, one at a time in the order they were
+/// queued, so a slow disk never holds up the games.
+pub struct StorageWriter {
+    repository: Arc<dyn GameRepository>,
+    writes: UnboundedSender<StorageWrite>,
+}
+
+impl StorageWriter {
+    pub fn new(repository: Box<dyn GameRepository>) -> Self {
+        let repository: Arc<dyn GameRepository> = Arc::from(repository);
+        let (writes, mut queue) = mpsc::unbounded_channel();
+        let writer = repository.clone();
+        std::thread::spawn(move || {
+            while let Some(write) = queue.blocking_recv() {
+                match write {
+                    StorageWrite::Save(game_state) => {
+                        if let Err(e) = writer.save(&game_state) {
+                            eprintln!("Error saving game {}: {:?}", game_state.id, e);
+                        }
+                    }
+                    StorageWrite::Remove(game_id) => {
+                        if let Err(e) = writer.remove(&game_id) {
+                            eprintln!("Error removing game {}: {:?}", game_id, e);
+                        }
+                    }
+                    StorageWrite::Flush(done) => {
+                        let _ = done.send(());
+                    }
+                }
+            }
+        });
+        StorageWriter { repository, writes }
+    }
+
+    pub fn repository(&self) -> &dyn GameRepository {
+        self.repository.as_ref()
+    }
+
+    pub fn save(&self, game_state: &GameState) {
+        self.queue(StorageWrite::Save(Box::new(game_state.clone())));
+    }
+
+    pub fn remove(&self, game_id: &str) {
+        self.queue(StorageWrite::Remove(game_id.to_string()));
+    }
+
+    /// Resolves once every write queued so far is done.
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        self.queue(StorageWrite::Flush(done));
+        let _ = flushed.await;
+    }
+
+    fn queue(&self, write: StorageWrite) {
+        if self.writes.send(write).is_err() {
+            eprintln!("Storage writer stopped, dropping a write");
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum LogEntry {
+    Save { game: Box<GameState> },
+    Remove { game_id: String },
+}
+
+/// Entries appended before the log is compacted again.
+pub const COMPACT_AFTER: usize = 1000;
+
+/// The open log and how many entries were appended since it was last compacted.
+struct LogFile {
+    file: File,
+    appended: usize,
+}
+
+/// Appends every change as a JSON line. The log is compacted each time it is loaded, and
+/// every `COMPACT_AFTER` entries while the server runs.
+pub struct JsonFileRepository {
+    path: PathBuf,
+    log: Mutex<LogFile>,
+}
+
+impl JsonFileRepository {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self { path, log: Mutex::new(LogFile { file, appended: 0 }) })
+    }
+
+    fn append(&self, entry: &LogEntry) -> io::Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        let mut log = lock(&self.log);
+        log.file.write_all(line.as_bytes())?;
+        log.file.flush()?;
+        log.appended += 1;
+        if log.appended >= COMPACT_AFTER {
+            self.compact(&mut log)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the log with only the latest state of each remaining game, and return those games.
+    fn compact(&self, log: &mut LogFile) -> io::Result<Vec<GameState>> {
+        let mut games: HashMap<String, GameState> = HashMap::new();
+
+        for line in BufReader::new(File::open(&self.path)?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<LogEntry>(&line) {
+                Ok(LogEntry::Save { game }) => { games.insert(game.id.clone(), *game); }
+                Ok(LogEntry::Remove { game_id }) => { games.remove(&game_id); }
+                // A crash mid-write leaves a truncated last line, skip it.
+                Err(e) => eprintln!("Skipping unreadable storage entry: {:?}", e),
+            }
+        }
+
+        let compacted_path = self.path.with_extension("compact");
+        {
+            let mut compacted = File::create(&compacted_path)?;
+            for game in games.values() {
+                let mut line = serde_json::to_string(&LogEntry::Save { game: Box::new(game.clone()) })?;
+                line.push('\n');
+                compacted.write_all(line.as_bytes())?;
+            }
+            compacted.sync_all()?;
+        }
+        std::fs::rename(&compacted_path, &self.path)?;
+        log.file = OpenOptions::new().append(true).open(&self.path)?;
+        log.appended = 0;
+
+        Ok(games.into_values().collect())
+    }
+}
+
+impl GameRepository for JsonFileRepository {
+    fn save(&self, game_state: &GameState) -> io::Result<()> {
+        self.append(&LogEntry::Save { game: Box::new(game_state.clone()) })
+    }
+
+    fn remove(&self, game_id: &str) -> io::Result<()> {
+        self.append(&LogEntry::Remove { game_id: game_id.to_string() })
+    }
+
+    fn load_all(&self) -> io::Result<Vec<GameState>> {
+        let mut log = lock(&self.log);
+        self.compact(&mut log)
+    }
+}
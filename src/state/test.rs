@@ -0,0 +1,871 @@
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::engine::bot::BotDifficulty;
+    use crate::engine::game::{Game, GamePhase};
+    use crate::engine::rules::RuleSet;
+    use crate::handlers::error::GameError;
+    use crate::handlers::cluster::{replay_from_host, run_cluster_node};
+    use crate::handlers::game::{GameConnection, apply_game_request, broadcast_game_message, check_watch, handle_socket_messages, play_timed_out_turn, seat_quick_match, shut_down};
+    use crate::protocol::{ChatLine, Encoding, ErrorCode, GameRequest, GameRequestAction, GameData, GameEvent, GameEventType, PlayerData, ServerMessage, SocketOptions};
+    use crate::state::actor::{spawn_game, GameServices};
+    use crate::state::chat::{ChatFilter, ChatLog, WordFilter, CHAT_HISTORY, RATE_LIMIT, RATE_WINDOW_MS};
+    use crate::routes::game::create_router;
+    use crate::state::cluster::{ClusterBackend, ClusterMessage, ClusterNode, ConnectionKind, LocalCluster, RelayedFrame};
+    use crate::state::matchmaking::{Matchmaker, QueuedPlayer};
+    use crate::state::state::{GameExpiry, GameManager, GameState, GameStateStatus, LobbyAccess, LobbyFeed, PlayerConnection, ViewHistory, MAX_SPECTATORS};
+    use axum::extract::ws::Message;
+    use crate::state::storage::{GameRepository, InMemoryRepository, JsonFileRepository, COMPACT_AFTER};
+    use std::sync::Arc;
+    use chrono::Utc;
+    use futures_util::{SinkExt, StreamExt};
+    use axum::http::StatusCode;
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite;
+    use tower_http::cors::CorsLayer;
+    use tokio::sync::RwLock;
+    use uuid::Uuid;
+
+    fn create_game_state(id: &str) -> GameState {
+        let players: HashMap<Uuid, PlayerConnection> = ["Player 0", "Player 1"].iter().map(|name| {
+            (Uuid::new_v4(), PlayerConnection {
+                name: name.to_string(),
+                sender: None,
+                connection_id: Uuid::new_v4(),
+                bot: None,
+                encoding: Default::default(),
+                updates: Default::default(),
+                views: Default::default(),
+            })
+        }).collect();
+        let game = Game::new(players.keys().cloned().collect(), RuleSet::default());
+
+        GameState {
+            id: id.to_string(),
+            status: GameStateStatus::InProgress,
+            rules: RuleSet::default(),
+            match_settings: Default::default(),
+            access: Default::default(),
+            date_created: Default::default(),
+            last_updated: Default::default(),
+            host: None,
+            locked: false,
+            game_match: None,
+            game: Some(game),
+            round_logs: vec![],
+            past_matches: vec![],
+            rematch_votes: Default::default(),
+            ready: Default::default(),
+            starts_at: None,
+            chat: Default::default(),
+            turn_deadline: None,
+            timeouts: Default::default(),
+            players,
+            spectators: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_json_file_repository() {
+        let path = std::env::temp_dir().join(format!("fortyone-{}.jsonl", Uuid::new_v4()));
+        let repository = JsonFileRepository::open(&path).unwrap();
+
+        let game_state = create_game_state("first");
+        repository.save(&game_state).unwrap();
+        repository.save(&create_game_state("second")).unwrap();
+        repository.remove("second").unwrap();
+
+        let repository = JsonFileRepository::open(&path).unwrap();
+        let games = repository.load_all().unwrap();
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].id, "first");
+        assert_eq!(games[0].status, GameStateStatus::InProgress);
+        assert_eq!(games[0].players.len(), 2);
+        assert!(games[0].players.values().all(|player| player.sender.is_none()));
+        assert_eq!(games[0].game.as_ref().unwrap().deck, game_state.game.as_ref().unwrap().deck);
+
+        // The log is compacted while running too, not only when loaded.
+        for _ in 0..COMPACT_AFTER {
+            repository.save(&game_state).unwrap();
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+
+        // Compaction keeps the remaining game and the log stays appendable.
+        repository.remove("first").unwrap();
+        assert!(repository.load_all().unwrap().is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn test_config() -> Config {
+        Config {
+            server_address: "127.0.0.1:0".to_string(),
+            allowed_origin: "*".to_string(),
+            session_secret: "secret".to_string(),
+            fixed_session_secret: true,
+            admin_token: None,
+            reconnect_grace_secs: 30,
+            matchmaking_timeout_secs: 30,
+            chat_blocked_words: vec![],
+            gc_interval_secs: 60,
+            idle_lobby_ttl_secs: 1800,
+            abandoned_game_ttl_secs: 600,
+            finished_game_ttl_secs: 600,
+            storage_path: None,
+            node_id: "node".to_string(),
+            local_cluster_addresses: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shut_down() {
+        let path = std::env::temp_dir().join(format!("fortyone-{}.jsonl", Uuid::new_v4()));
+        let cluster = || Arc::new(ClusterNode::new("node".to_string(), Arc::new(LocalCluster::default())));
+        let repository = Box::new(JsonFileRepository::open(&path).unwrap());
+        let state = Arc::new(RwLock::new(GameManager::new(&test_config(), repository, cluster())));
+        let game_id = state.write().await.create_game(RuleSet::default(), Default::default(), LobbyAccess::default()).id;
+        let handle = state.read().await.game(&game_id).unwrap();
+        // A change not saved yet is saved on the way down.
+        handle.call(|game_state, _| game_state.locked = true).await.unwrap();
+
+        shut_down(state.clone()).await;
+        assert!(state.read().await.shutting_down);
+        // The game takes no more requests once saved.
+        assert!(handle.call(|game_state, _| game_state.locked = false).await.is_none());
+
+        let repository = Box::new(JsonFileRepository::open(&path).unwrap());
+        let restored = GameManager::new(&test_config(), repository, cluster());
+        let locked = restored.game(&game_id).unwrap().call(|game_state, _| game_state.locked).await;
+        assert_eq!(locked, Some(true));
+
+        // Players are only told to rejoin when the games are stored and their tokens stay valid.
+        assert!(restored.resumable());
+        let random_secret = Config { fixed_session_secret: false, ..test_config() };
+        assert!(!GameManager::new(&random_secret, Box::new(JsonFileRepository::open(&path).unwrap()), cluster()).resumable());
+        assert!(!GameManager::new(&test_config(), Box::new(InMemoryRepository::default()), cluster()).resumable());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_reset_to_lobby() {
+        let mut game_state = create_game_state("rematch");
+        game_state.status = GameStateStatus::Finished;
+        let voter = *game_state.players.keys().next().unwrap();
+        game_state.rematch_votes.insert(voter);
+        let log = game_state.game.as_ref().unwrap().log.clone();
+        game_state.round_logs.push(log.clone());
+
+        game_state.reset_to_lobby();
+        assert_eq!(game_state.past_matches, vec![vec![log]]);
+        assert!(game_state.round_logs.is_empty());
+        assert!(game_state.status == GameStateStatus::Lobby);
+        assert!(game_state.game.is_none());
+        assert!(game_state.game_match.is_none());
+        assert!(game_state.rematch_votes.is_empty());
+        assert_eq!(game_state.players.len(), 2);
+    }
+
+    #[test]
+    fn test_rematch_deals() {
+        let mut game_state = create_game_state("reseed");
+        game_state.status = GameStateStatus::Lobby;
+        game_state.game = None;
+        game_state.match_settings.seed = Some(41);
+        let ids: Vec<Uuid> = game_state.players.keys().copied().collect();
+        game_state.host = Some(ids[0]);
+        let start = GameRequest::new(GameRequestAction::StartGame, None);
+        let mut deals = vec![];
+        for _ in 0..2 {
+            game_state.ready.extend(ids.iter().copied());
+            apply_game_request(&mut game_state, ids[0], &start).unwrap();
+            deals.push(game_state.game.as_ref().unwrap().deck.clone());
+            game_state.status = GameStateStatus::Finished;
+            game_state.reset_to_lobby();
+        }
+
+        // The rematch does not deal the decks of the match before, which its replay shows.
+        assert_ne!(deals[0], deals[1]);
+        assert!(game_state.match_settings.seed.is_none());
+    }
+
+    #[test]
+    fn test_rematch_votes() {
+        let mut game_state = create_game_state("votes");
+        game_state.status = GameStateStatus::Finished;
+        let ids: Vec<Uuid> = game_state.players.keys().copied().collect();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        for id in &ids {
+            game_state.players.get_mut(id).unwrap().sender = Some(sender.clone());
+        }
+        let rematch = GameRequest::new(GameRequestAction::Rematch, None);
+
+        // Every connected player has to vote.
+        apply_game_request(&mut game_state, ids[0], &rematch).unwrap();
+        assert_eq!(game_state.status, GameStateStatus::Finished);
+        let votes = last_message(&mut receiver).unwrap();
+        assert_eq!((votes["data"]["votes"].as_array().unwrap().len(), votes["data"]["needed"].as_u64()), (1, Some(2)));
+
+        // A disconnected player is not waited for.
+        game_state.players.get_mut(&ids[1]).unwrap().sender = None;
+        apply_game_request(&mut game_state, ids[0], &rematch).unwrap();
+        assert_eq!(game_state.status, GameStateStatus::Lobby);
+        assert_eq!(last_message(&mut receiver).unwrap()["data"]["accepted"], true);
+        assert_eq!(apply_game_request(&mut game_state, ids[0], &rematch), Err(ErrorCode::GameNotFinished));
+    }
+
+    #[test]
+    fn test_host_actions() {
+        let mut game_state = create_game_state("host");
+        game_state.status = GameStateStatus::Lobby;
+        game_state.game = None;
+        let id_of = |game_state: &GameState, name: &str| *game_state.players.iter().find(|(_, player)| player.name == name).unwrap().0;
+        let (host, guest) = (id_of(&game_state, "Player 0"), id_of(&game_state, "Player 1"));
+        let (sender, mut host_rx) = tokio::sync::mpsc::unbounded_channel();
+        game_state.players.get_mut(&host).unwrap().sender = Some(sender);
+        game_state.players.get_mut(&guest).unwrap().sender = Some(tokio::sync::mpsc::unbounded_channel().0);
+        // A bot, and a player holding their seat while disconnected.
+        for (name, bot) in [("Bot", Some(BotDifficulty::default())), ("Away", None)] {
+            let player = PlayerConnection { name: name.to_string(), sender: None, bot, ..game_state.players[&host].clone() };
+            game_state.players.insert(Uuid::new_v4(), player);
+        }
+        let targeting = |action, name: &str| GameRequest { player: Some(name.to_string()), ..GameRequest::new(action, None) };
+
+        // Only the host acts, anyone does in a game without a host.
+        game_state.host = Some(host);
+        let lock = GameRequest::new(GameRequestAction::LockLobby, None);
+        assert_eq!(apply_game_request(&mut game_state, guest, &lock), Err(ErrorCode::NotHost));
+        game_state.host = None;
+        assert_eq!(apply_game_request(&mut game_state, guest, &lock), Ok(()));
+        game_state.host = Some(host);
+
+        // The host role only goes to another connected human.
+        for name in ["Bot", "Away", "Player 0"] {
+            assert_eq!(apply_game_request(&mut game_state, host, &targeting(GameRequestAction::TransferHost, name)), Err(ErrorCode::InvalidTarget));
+        }
+        assert_eq!(game_state.host, Some(host));
+
+        // Rules never leave a seated player without a seat.
+        let rules = |max_players| GameRequest { rules: Some(RuleSet { max_players, ..RuleSet::default() }), ..GameRequest::new(GameRequestAction::UpdateRules, None) };
+        assert_eq!(apply_game_request(&mut game_state, host, &rules(3)), Err(ErrorCode::InvalidRules));
+        assert_eq!(apply_game_request(&mut game_state, host, &rules(5)), Ok(()));
+        assert_eq!(game_state.rules.max_players, 5);
+
+        // A kicked player's socket is closed and their seat freed.
+        assert_eq!(apply_game_request(&mut game_state, host, &targeting(GameRequestAction::TransferHost, "Player 1")), Ok(()));
+        assert_eq!(game_state.host, Some(guest));
+        assert_eq!(apply_game_request(&mut game_state, guest, &targeting(GameRequestAction::Kick, "Player 0")), Ok(()));
+        assert!(!game_state.players.contains_key(&host));
+        let mut closed = false;
+        while let Ok(message) = host_rx.try_recv() {
+            closed |= matches!(message, Message::Close(Some(frame)) if frame.code == 1008);
+        }
+        assert!(closed);
+    }
+
+    #[test]
+    fn test_kick_after_round() {
+        for status in [GameStateStatus::InProgress, GameStateStatus::Finished] {
+            let mut game_state = create_game_state("ended");
+            game_state.status = status;
+            let host = *game_state.players.iter().find(|(_, player)| player.name == "Player 0").unwrap().0;
+            game_state.host = Some(host);
+            let game = game_state.game.as_mut().unwrap();
+            game.phase = GamePhase::GameEnded;
+            game.current_turn = game.players.iter().position(|player| player.id != host).unwrap();
+
+            // The kicked player's turn is not played, the ended round stays ended.
+            let kick = GameRequest { player: Some("Player 1".to_string()), ..GameRequest::new(GameRequestAction::Kick, None) };
+            assert_eq!(apply_game_request(&mut game_state, host, &kick), Ok(()));
+            let game = game_state.game.as_ref().unwrap();
+            assert_eq!(game.phase, GamePhase::GameEnded);
+            assert_eq!(game.players.len(), 1);
+            assert_eq!(game_state.players.len(), 1);
+        }
+    }
+
+    /// The last JSON message left in `receiver`.
+    fn last_message(receiver: &mut tokio::sync::mpsc::UnboundedReceiver<Message>) -> Option<serde_json::Value> {
+        let mut last = None;
+        while let Ok(message) = receiver.try_recv() {
+            if let Message::Text(text) = message {
+                last = Some(serde_json::from_str(&text).unwrap());
+            }
+        }
+        last
+    }
+
+    #[test]
+    fn test_spectators() {
+        let mut game_state = create_game_state("spectators");
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        game_state.spectators.insert(Uuid::new_v4(), PlayerConnection {
+            name: "Spectator".to_string(),
+            sender: Some(sender),
+            connection_id: Uuid::new_v4(),
+            bot: None,
+            encoding: Default::default(),
+            updates: Default::default(),
+            views: Default::default(),
+        });
+
+        broadcast_game_message(&mut game_state, GameEvent { event_type: GameEventType::Watch, from: None, to: None });
+        let message = last_message(&mut receiver).unwrap();
+        assert!(message["data"]["player_pos"].is_null());
+        for player in message["data"]["players"].as_array().unwrap() {
+            assert!(player["hand"].as_array().unwrap().iter().all(|card| card == ""));
+        }
+
+        assert!(check_watch(&game_state).is_ok());
+        while game_state.spectators.len() < MAX_SPECTATORS {
+            let spectator = game_state.spectators.values().next().unwrap().clone();
+            game_state.spectators.insert(Uuid::new_v4(), spectator);
+        }
+        assert!(matches!(check_watch(&game_state), Err(GameError::SpectatorsFull)));
+    }
+
+    #[test]
+    fn test_turn_deadline() {
+        let mut game_state = create_game_state("timer");
+        game_state.update_turn_deadline();
+        assert!(game_state.turn_deadline.is_none());
+
+        game_state.rules.turn_timeout_secs = Some(30);
+        game_state.update_turn_deadline();
+        let deadline = game_state.turn_deadline.clone().unwrap();
+        let game = game_state.game.as_mut().unwrap();
+        assert_eq!(deadline.player_id, game.players[game.current_turn].id);
+
+        // Drawing keeps the deadline, passing the turn starts a new one.
+        let player_id = deadline.player_id;
+        game.draw(&player_id).unwrap();
+        let card = game.players[game.current_turn].hand[0].clone();
+        game_state.update_turn_deadline();
+        assert_eq!(game_state.turn_deadline.as_ref().unwrap().expires_at, deadline.expires_at);
+
+        game_state.game.as_mut().unwrap().discard(&player_id, card).unwrap();
+        game_state.update_turn_deadline();
+        assert_ne!(game_state.turn_deadline.as_ref().unwrap().player_id, player_id);
+
+        game_state.status = GameStateStatus::Finished;
+        game_state.update_turn_deadline();
+        assert!(game_state.turn_deadline.is_none());
+    }
+
+    #[test]
+    fn test_timeout_kick() {
+        let mut game_state = create_game_state("kick");
+        game_state.rules.max_timeouts = Some(1);
+        let game = game_state.game.as_ref().unwrap();
+        let player_id = game.players[game.current_turn].id;
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        game_state.players.get_mut(&player_id).unwrap().sender = Some(sender);
+
+        play_timed_out_turn(&mut game_state, player_id);
+        assert!(!game_state.players.contains_key(&player_id));
+        let mut closed = false;
+        while let Ok(message) = receiver.try_recv() {
+            closed |= matches!(message, Message::Close(Some(frame)) if frame.code == 1008);
+        }
+        assert!(closed);
+    }
+
+    #[test]
+    fn test_migrate_host() {
+        let mut game_state = create_game_state("host");
+        let id_of = |game_state: &GameState, name: &str| {
+            *game_state.players.iter().find(|(_, player)| player.name == name).unwrap().0
+        };
+        let (first, second) = (id_of(&game_state, "Player 0"), id_of(&game_state, "Player 1"));
+
+        // Nobody is connected, so nobody can take over.
+        assert_eq!(game_state.migrate_host(), None);
+
+        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+        game_state.players.get_mut(&second).unwrap().sender = Some(sender.clone());
+        assert_eq!(game_state.migrate_host(), Some(second));
+        game_state.players.get_mut(&first).unwrap().sender = Some(sender);
+        assert_eq!(game_state.migrate_host(), None);
+        assert_eq!(game_state.host, Some(second));
+
+        game_state.players.get_mut(&second).unwrap().sender = None;
+        assert_eq!(game_state.migrate_host(), Some(first));
+
+        game_state.players.remove(&first);
+        assert_eq!(game_state.migrate_host(), None);
+        assert_eq!(game_state.host, Some(first));
+    }
+
+    #[test]
+    fn test_start_countdown() {
+        let mut game_state = create_game_state("ready");
+        game_state.status = GameStateStatus::Lobby;
+        game_state.game = None;
+        let ids: Vec<Uuid> = game_state.players.keys().copied().collect();
+
+        game_state.ready.insert(ids[0]);
+        assert!(!game_state.all_ready());
+        game_state.players.get_mut(&ids[1]).unwrap().bot = Some(Default::default());
+        assert!(game_state.all_ready());
+
+        // Without a countdown in the rules the lobby waits for the host.
+        assert!(!game_state.update_start_countdown());
+        assert_eq!(game_state.starts_at, None);
+
+        game_state.rules.start_countdown_secs = Some(10);
+        assert!(game_state.update_start_countdown());
+        let starts_at = game_state.starts_at.unwrap();
+        assert!(!game_state.update_start_countdown());
+        assert_eq!(game_state.starts_at, Some(starts_at));
+
+        game_state.ready.clear();
+        assert!(game_state.update_start_countdown());
+        assert_eq!(game_state.starts_at, None);
+    }
+
+    #[test]
+    fn test_chat_log() {
+        let mut chat = ChatLog::default();
+        let (talker, other) = (Uuid::new_v4(), Uuid::new_v4());
+        for i in 0..RATE_LIMIT as u64 {
+            assert!(chat.allow(talker, i));
+        }
+        assert!(!chat.allow(talker, RATE_LIMIT as u64));
+        assert!(chat.allow(other, RATE_LIMIT as u64));
+        // The first message leaves the window.
+        assert!(chat.allow(talker, RATE_WINDOW_MS));
+        assert!(!chat.allow(talker, RATE_WINDOW_MS));
+
+        for i in 0..CHAT_HISTORY as u64 + 1 {
+            chat.push(ChatLine { name: "Player 0".to_string(), text: Some(i.to_string()), emote: None, sent_at: i });
+        }
+        let history = chat.history();
+        assert_eq!(history.len(), CHAT_HISTORY);
+        assert_eq!(history[0].text.as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn test_word_filter() {
+        let filter = WordFilter::new(&["darn".to_string()]);
+        assert_eq!(filter.filter("Darn, that bin!").as_deref(), Some("****, that bin!"));
+        assert_eq!(filter.filter("darned").as_deref(), Some("darned"));
+        assert_eq!(WordFilter::default().filter("darn").as_deref(), Some("darn"));
+    }
+
+    #[test]
+    fn test_expired() {
+        let expiry = GameExpiry {
+            interval: Duration::from_secs(60),
+            idle_lobby: Duration::from_secs(1800),
+            abandoned: Duration::from_secs(600),
+            finished: Duration::from_secs(300),
+        };
+        let mut game_state = create_game_state("expiry");
+        let now = Utc::now();
+        game_state.last_updated = now - chrono::Duration::seconds(400);
+        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+        game_state.players.values_mut().next().unwrap().sender = Some(sender);
+
+        assert_eq!(game_state.expired(now, &expiry), None);
+        game_state.status = GameStateStatus::Finished;
+        assert_eq!(game_state.expired(now, &expiry), Some("The game is over"));
+
+        game_state.status = GameStateStatus::InProgress;
+        for player in game_state.players.values_mut() {
+            player.sender = None;
+        }
+        assert_eq!(game_state.expired(now, &expiry), None);
+        game_state.last_updated = now - chrono::Duration::seconds(600);
+        assert_eq!(game_state.expired(now, &expiry), Some("Every player left"));
+    }
+
+    #[test]
+    fn test_lobby_access() {
+        let open = LobbyAccess::default();
+        assert!(open.check_password(None));
+        assert!(open.check_invite(None));
+
+        let locked = LobbyAccess::new(Some("hunter2"), true);
+        assert_ne!(locked.password_hash.as_deref(), Some("hunter2"));
+        assert!(locked.check_password(Some(&"hunter2".to_string())));
+        assert!(!locked.check_password(Some(&"hunter3".to_string())));
+        assert!(!locked.check_password(None));
+        assert!(locked.check_invite(locked.invite_code.as_ref()));
+        assert!(!locked.check_invite(Some(&"guess".to_string())));
+        assert!(!locked.check_invite(None));
+    }
+
+    #[test]
+    fn test_lobby_feed() {
+        let mut game_state = create_game_state("lobby");
+        assert!(game_state.lobby_summary().is_none());
+        game_state.status = GameStateStatus::Lobby;
+        let summary = game_state.lobby_summary().unwrap();
+        assert_eq!((summary.players, summary.max_players, summary.password_protected), (2, 4, false));
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Message>();
+        let mut feed = LobbyFeed::default();
+        feed.watchers.insert(Uuid::new_v4(), tx);
+        let mut received = || match rx.try_recv() {
+            Ok(Message::Text(text)) => Some(serde_json::from_str::<serde_json::Value>(&text).unwrap()),
+            _ => None,
+        };
+
+        feed.publish("lobby", game_state.lobby_summary());
+        assert_eq!(received().unwrap()["message_type"], "lobby_update");
+        // Saving an unchanged lobby pushes nothing.
+        feed.publish("lobby", game_state.lobby_summary());
+        assert!(received().is_none());
+
+        // Private lobbies are never listed.
+        game_state.access = LobbyAccess::new(None, true);
+        feed.publish("lobby", game_state.lobby_summary());
+        let removed = received().unwrap();
+        assert_eq!(removed["message_type"], "lobby_removed");
+        assert_eq!(removed["data"]["game_id"], "lobby");
+        feed.publish("lobby", game_state.lobby_summary());
+        assert!(received().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_game_actor() {
+        let cluster = Arc::new(ClusterNode::new("node".to_string(), Arc::new(LocalCluster::default())));
+        let services = Arc::new(GameServices::new(Box::new(InMemoryRepository::default()), Box::new(WordFilter::default()), cluster));
+        let first = spawn_game(create_game_state("first"), services.clone());
+        let second = spawn_game(create_game_state("second"), services.clone());
+
+        // Commands run on the game's task, saving persists the changed state.
+        let status = first.call(|game_state, ctx| {
+            game_state.status = GameStateStatus::Lobby;
+            ctx.save(game_state);
+            game_state.status.clone()
+        }).await;
+        assert_eq!(status, Some(GameStateStatus::Lobby));
+        services.storage().flush().await;
+        assert_eq!(services.repository().load_all().unwrap()[0].status, GameStateStatus::Lobby);
+        assert_eq!(services.lobby_feed().lobbies().len(), 1);
+
+        // A panicking command only ends its own game.
+        assert_eq!(first.call(|_, _| -> () { panic!("broken command") }).await, None);
+        assert!(first.call(|game_state, _| game_state.id.clone()).await.is_none());
+        assert!(first.is_closed());
+        assert_eq!(second.call(|game_state, _| game_state.id.clone()).await.as_deref(), Some("second"));
+        // The lobby is delisted once the panic is noticed.
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(services.lobby_feed().lobbies().is_empty());
+
+        // Removing a game stops its task.
+        second.call(|game_state, ctx| ctx.remove(game_state)).await.unwrap();
+        assert!(second.call(|_, _| ()).await.is_none());
+        services.storage().flush().await;
+        assert!(services.repository().load_all().unwrap().iter().all(|game_state| game_state.id != "second"));
+    }
+
+    #[tokio::test]
+    async fn test_socket_of_stopped_game() {
+        let cluster = Arc::new(ClusterNode::new("node".to_string(), Arc::new(LocalCluster::default())));
+        let services = Arc::new(GameServices::new(Box::new(InMemoryRepository::default()), Box::new(WordFilter::default()), cluster));
+        let handle = spawn_game(create_game_state("stopped"), services);
+        handle.call(|game_state, ctx| ctx.remove(game_state)).await.unwrap();
+
+        // A request to a game whose task is gone closes the socket with a reason.
+        let (tx, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let requests = futures_util::stream::iter([Ok(Message::Text(r#"{"action":"draw"}"#.into()))]).boxed();
+        handle_socket_messages(requests, &tx, Encoding::Json, &handle, Uuid::new_v4()).await;
+        let mut messages = vec![];
+        while let Ok(message) = receiver.try_recv() {
+            messages.push(message);
+        }
+        assert!(matches!(&messages[..], [Message::Text(text), Message::Close(Some(_))] if text.contains("game_closed")));
+    }
+
+    #[tokio::test]
+    async fn test_join_checked_when_seated() {
+        let cluster = Arc::new(ClusterNode::new("node".to_string(), Arc::new(LocalCluster::default())));
+        let state = Arc::new(RwLock::new(GameManager::new(&test_config(), Box::new(InMemoryRepository::default()), cluster)));
+        let game_id = state.write().await.create_game(RuleSet { max_players: 2, ..Default::default() }, Default::default(), LobbyAccess::default()).id;
+        let handle = state.read().await.game(&game_id).unwrap();
+        let options = SocketOptions { protocol_version: 1, encoding: Default::default(), updates: Default::default() };
+
+        // Each passed the handshake, someone took the name or the last seat before they were seated.
+        handle.call(|game_state, _| {
+            let (_, player) = create_game_state("other").players.into_iter().next().unwrap();
+            game_state.players.insert(Uuid::new_v4(), PlayerConnection { name: "Taken".to_string(), ..player });
+        }).await.unwrap();
+        for name in ["Taken", "Free", "Late"] {
+            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+            let join = GameConnection::Player { handle: handle.clone(), player_id: Uuid::new_v4(), player_name: name.to_string(), options };
+            join.serve(state.clone(), tx, futures_util::stream::empty().boxed()).await;
+            let refused = matches!(rx.try_recv(), Ok(Message::Close(Some(frame))) if frame.code == 1008);
+            assert_eq!(refused, name != "Free", "{}", name);
+        }
+        // The seat of the player who got in is held for the reconnect grace period.
+        let mut names = handle.call(|game_state, _| game_state.players.values().map(|player| player.name.clone()).collect::<Vec<_>>()).await.unwrap();
+        names.sort();
+        assert_eq!(names, ["Free", "Taken"]);
+    }
+
+    #[test]
+    fn test_local_cluster() {
+        let backend = Arc::new(LocalCluster::default());
+        assert_eq!(backend.claim("game", "a"), "a");
+        assert_eq!(backend.claim("game", "b"), "a");
+        // Only the host releases its game.
+        backend.release("game", "b");
+        assert_eq!(backend.host("game").as_deref(), Some("a"));
+        backend.release("game", "a");
+        assert!(backend.host("game").is_none());
+
+        let a = ClusterNode::new("a".to_string(), backend.clone());
+        let b = ClusterNode::new("b".to_string(), backend.clone());
+        assert_eq!(a.claim("game"), Ok(()));
+        assert_eq!(b.claim("game"), Err("a".to_string()));
+        a.release("game");
+        let (mut inbox, mut lobbies) = b.subscribe();
+        // Subscribing asks the other nodes for their listings.
+        assert_eq!(ClusterMessage::decode(&lobbies.try_recv().unwrap()), Some(ClusterMessage::Hello { node_id: "b".to_string() }));
+        let connection = Uuid::new_v4();
+        let frame = RelayedFrame::from_message(Message::Close(None)).unwrap();
+        a.send("b", &ClusterMessage::Frame { connection, frame: frame.clone() });
+        a.publish_lobby("game", None);
+        let received = ClusterMessage::decode(&inbox.try_recv().unwrap()).unwrap();
+        assert_eq!(received, ClusterMessage::Frame { connection, frame });
+        assert!(matches!(ClusterMessage::decode(&lobbies.try_recv().unwrap()), Some(ClusterMessage::Lobby { node_id, .. }) if node_id == "a"));
+        assert!(inbox.try_recv().is_err());
+
+        // Frames reach a relayed connection until it is forgotten.
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Message>();
+        b.relay(connection, tx);
+        b.deliver(connection, RelayedFrame::Text("hello".to_string()).into_message());
+        assert!(matches!(rx.try_recv(), Ok(Message::Text(text)) if text == "hello"));
+        b.forget(connection);
+        b.deliver(connection, RelayedFrame::Text("gone".to_string()).into_message());
+        assert!(rx.try_recv().is_err());
+    }
+
+    /// Serve a node of the cluster on a port of its own, like `run_node` does.
+    async fn start_node(node_id: &str, backend: Arc<LocalCluster>) -> (Arc<RwLock<GameManager>>, SocketAddr) {
+        let cluster = Arc::new(ClusterNode::new(node_id.to_string(), backend));
+        let state = Arc::new(RwLock::new(GameManager::new(&test_config(), Box::new(InMemoryRepository::default()), cluster)));
+        tokio::spawn(run_cluster_node(state.clone()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let router = create_router(state.clone(), CorsLayer::new());
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        (state, address)
+    }
+
+    /// Next JSON message of a client socket.
+    async fn next_json<S: futures_util::Stream<Item = Result<tungstenite::Message, tungstenite::Error>> + Unpin>(socket: &mut S) -> serde_json::Value {
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(5), socket.next()).await.unwrap().unwrap().unwrap();
+            if let tungstenite::Message::Text(text) = message {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cluster_nodes() {
+        let backend = Arc::new(LocalCluster::default());
+        let (a, _) = start_node("a", backend.clone()).await;
+        let game_id = a.write().await.create_game(RuleSet { max_players: 2, ..Default::default() }, Default::default(), LobbyAccess::default()).id;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // A node started after the game was listed still gets its listing.
+        let (b, b_address) = start_node("b", backend.clone()).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(b.read().await.services.lobby_feed().summary(&game_id).is_some());
+        assert!(b.read().await.game(&game_id).is_none());
+
+        // A player joining through node B plays in the game hosted on node A.
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/{}/join?player_name=Remote", b_address, game_id)).await.unwrap();
+        assert_eq!(next_json(&mut socket).await["message_type"], "session");
+        for action in ["add_bot", "ready", "start_game"] {
+            socket.send(tungstenite::Message::Text(format!(r#"{{"action":"{}"}}"#, action).into())).await.unwrap();
+        }
+        loop {
+            let message = next_json(&mut socket).await;
+            let data = &message["data"];
+            if message["message_type"] == "game_event" && data["current_turn"] == data["player_pos"] && data["current_phase"] == "p1" {
+                break;
+            }
+        }
+        socket.send(tungstenite::Message::Text(r#"{"action":"draw"}"#.into())).await.unwrap();
+        loop {
+            let message = next_json(&mut socket).await;
+            if message["message_type"] == "game_event" && message["data"]["current_phase"] == "p2" {
+                break;
+            }
+        }
+        let handle = a.read().await.game(&game_id).unwrap();
+        let phase = handle.call(|game_state, _| game_state.game.as_ref().map(|game| game.phase.clone())).await.unwrap();
+        assert_eq!(phase, Some(GamePhase::P2));
+
+        // Replays are exported by the host too.
+        let refused = replay_from_host(b.clone(), game_id.clone(), String::new()).await.unwrap_err();
+        assert_eq!(refused.0, StatusCode::BAD_REQUEST);
+        let missing = replay_from_host(b.clone(), "missing".to_string(), String::new()).await.unwrap_err();
+        assert_eq!(missing.0, StatusCode::NOT_FOUND);
+
+        // A relayed socket the other node never upgraded takes no seat.
+        let ghost = ClusterNode::new("ghost".to_string(), backend.clone());
+        let (mut inbox, _lobbies) = ghost.subscribe();
+        let (lobby_id, lobby) = {
+            let mut game_manager = a.write().await;
+            let id = game_manager.create_game(RuleSet::default(), Default::default(), LobbyAccess::default()).id;
+            (id.clone(), game_manager.game(&id).unwrap())
+        };
+        let connection = Uuid::new_v4();
+        let params = HashMap::from([("player_name".to_string(), "Ghost".to_string())]);
+        ghost.send("a", &ClusterMessage::Open { connection, node_id: "ghost".to_string(), kind: ConnectionKind::Join, game_id: lobby_id, params });
+        // Node A answered the ghost's hello with its listings first.
+        let opened = loop {
+            match ClusterMessage::decode(&inbox.recv().await.unwrap()).unwrap() {
+                ClusterMessage::Lobby { .. } => continue,
+                message => break message,
+            }
+        };
+        assert_eq!(opened, ClusterMessage::Opened { connection, error: None });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(lobby.call(|game_state, _| game_state.players.len()).await, Some(0));
+        ghost.send("a", &ClusterMessage::Closed { connection });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(lobby.call(|game_state, _| game_state.players.len()).await, Some(0));
+
+        // Stopping a node gives its games up, so they can be resumed under the same node id.
+        let client = tokio::spawn(async move { while socket.next().await.is_some() {} });
+        shut_down(a).await;
+        client.await.unwrap();
+        assert!(backend.host(&game_id).is_none());
+        assert!(b.read().await.services.lobby_feed().summary(&game_id).is_none());
+    }
+
+    fn queued_player(name: &str) -> QueuedPlayer {
+        let (sender, _) = tokio::sync::mpsc::unbounded_channel();
+        let (matched, _) = tokio::sync::oneshot::channel();
+        let options = SocketOptions { protocol_version: 1, encoding: Default::default(), updates: Default::default() };
+        QueuedPlayer { player_id: Uuid::new_v4(), name: name.to_string(), connection_id: Uuid::new_v4(), sender, options, matched }
+    }
+
+    #[tokio::test]
+    async fn test_seat_quick_match() {
+        let cluster = Arc::new(ClusterNode::new("node".to_string(), Arc::new(LocalCluster::default())));
+        let services = Arc::new(GameServices::new(Box::new(InMemoryRepository::default()), Box::new(WordFilter::default()), cluster));
+        let mut lobby = create_game_state("quick");
+        lobby.status = GameStateStatus::Lobby;
+        lobby.players.clear();
+        lobby.game = None;
+        let handle = spawn_game(lobby.clone(), services.clone());
+
+        // A player who left the queue is skipped, their seat goes to a bot.
+        let (matched, matched_rx) = tokio::sync::oneshot::channel();
+        let waiting = QueuedPlayer { matched, ..queued_player("Waiting") };
+        let waiting_id = waiting.player_id;
+        let seats = handle.call(|game_state, ctx| {
+            seat_quick_match(game_state, ctx, "secret", 3, vec![queued_player("Gone"), waiting]);
+            game_state.players.values().map(|player| (player.name.clone(), player.bot.is_some())).collect::<Vec<_>>()
+        }).await.unwrap();
+        assert_eq!(matched_rx.await.as_deref(), Ok("quick"));
+        assert_eq!(seats.len(), 3);
+        assert_eq!(seats.iter().filter(|(_, bot)| !bot).count(), 1);
+        assert!(handle.call(move |game_state, _| game_state.players.contains_key(&waiting_id)).await.unwrap());
+
+        // Nobody is left to play the game.
+        let abandoned = spawn_game(lobby, services);
+        abandoned.call(|game_state, ctx| seat_quick_match(game_state, ctx, "secret", 2, vec![queued_player("Gone")])).await.unwrap();
+        assert!(abandoned.call(|_, _| ()).await.is_none());
+    }
+
+    #[test]
+    fn test_matchmaker() {
+        let mut matchmaker = Matchmaker::default();
+        let first = queued_player("First");
+        let first_id = first.player_id;
+        assert!(matchmaker.join(3, first).is_none());
+        assert!(matchmaker.join(2, queued_player("Other table")).is_none());
+        assert!(matchmaker.join(3, queued_player("Second")).is_none());
+        assert_eq!(matchmaker.waiting(3).len(), 2);
+
+        // The third player completes the table, in arrival order.
+        let table = matchmaker.join(3, queued_player("Third")).unwrap();
+        let names: Vec<&str> = table.iter().map(|player| player.name.as_str()).collect();
+        assert_eq!(names, ["First", "Second", "Third"]);
+        assert!(matchmaker.waiting(3).is_empty());
+
+        // A timeout of a player who was already seated takes no one.
+        assert!(matchmaker.take_waiting(3, &first_id).is_none());
+
+        let late = queued_player("Late");
+        let late_id = late.player_id;
+        matchmaker.join(3, late);
+        assert_eq!(matchmaker.take_waiting(3, &late_id).unwrap().len(), 1);
+
+        let leaving = queued_player("Leaving");
+        let leaving_id = leaving.player_id;
+        matchmaker.join(4, leaving);
+        assert_eq!(matchmaker.leave(&leaving_id), Some(4));
+        assert_eq!(matchmaker.leave(&leaving_id), None);
+        assert_eq!(matchmaker.waiting(2).len(), 1);
+    }
+
+    fn game_view(version: u64, bins: [&[&str]; 2]) -> GameData {
+        GameData {
+            version,
+            player_id: Uuid::nil(),
+            player_pos: Some(0),
+            num_of_players: 2,
+            card_left: 40 - version as u8,
+            current_turn: 0,
+            current_phase: GamePhase::P1,
+            turn_deadline: None,
+            rules: RuleSet::default(),
+            event: GameEvent { event_type: GameEventType::Draw, from: None, to: Some(0) },
+            players: bins.iter().enumerate().map(|(i, bin)| PlayerData {
+                name: format!("Player {}", i),
+                is_bot: false,
+                hand: vec![],
+                bin: bin.iter().map(|card| card.to_string()).collect(),
+            }).collect(),
+        }
+    }
+
+    #[test]
+    fn test_view_history() {
+        let mut views = ViewHistory::default();
+        assert!(matches!(views.snapshot(game_view(0, [&[], &[]])), ServerMessage::GameEvent { .. }));
+        // Nothing acknowledged yet, so nothing to diff against.
+        assert!(matches!(views.update(game_view(1, [&["H2"], &[]])), ServerMessage::GameEvent { .. }));
+
+        assert!(views.ack(1));
+        match views.update(game_view(2, [&["H2"], &["S5"]])) {
+            ServerMessage::GameDelta { data } => {
+                assert_eq!((data.base_version, data.version, data.card_left), (1, 2, 38));
+                assert_eq!(data.players.len(), 1);
+                assert_eq!(data.players[0].seat, 1);
+                assert_eq!(data.players[0].bin, Some(vec!["S5".to_string()]));
+                assert_eq!(data.players[0].hand, None);
+            }
+            message => panic!("Expected a delta, got {:?}", message),
+        }
+
+        // Unacknowledged views stay relative to the last acknowledged one.
+        match views.update(game_view(3, [&[], &["S5"]])) {
+            ServerMessage::GameDelta { data } => assert_eq!(data.base_version, 1),
+            message => panic!("Expected a delta, got {:?}", message),
+        }
+
+        // Versions that were never sent or are already dropped are refused.
+        assert!(!views.ack(7));
+        assert!(!views.ack(1));
+        assert!(views.ack(3));
+
+        // A seat change cannot be expressed as a delta.
+        let mut seats_changed = game_view(4, [&[], &["S5"]]);
+        seats_changed.players.pop();
+        seats_changed.num_of_players = 1;
+        assert!(matches!(views.update(seats_changed), ServerMessage::GameEvent { .. }));
+    }
+}
\ No newline at end of file
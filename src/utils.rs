@@ -1,5 +1,15 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
+type HmacSha256 = Hmac<Sha256>;
+
 pub fn generate_short_uuid() -> String {
     // Generate a random UUID
     let uuid = Uuid::new_v4();
@@ -7,4 +17,89 @@ pub fn generate_short_uuid() -> String {
     let uuid_bytes = uuid.as_bytes();
     let first_8_bytes: u64 = u64::from_be_bytes(uuid_bytes[0..8].try_into().unwrap());
     base62::encode(first_8_bytes)
+}
+
+/// Milliseconds since the Unix epoch.
+pub fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+/// Lock a mutex shared by every game, even after a panic while it was held. A game panicking half
+/// way through must not stop the others from using it.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Argon2 hash of a password with a random salt, in PHC string format, so stored lobbies never
+/// hold the password itself. Slow on purpose, call it off the async runtime.
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default().hash_password(password.as_bytes(), &salt).expect("Argon2 accepts any password").to_string()
+}
+
+/// Whether `password` matches a hash made by `hash_password`. As slow as hashing.
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+}
+
+/// Compare secrets in a time that does not depend on where they differ.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
+
+/// Sign a session token binding `player_id` to `game_id`, formatted as `<player_id>.<hex mac>`.
+pub fn sign_session_token(secret: &str, game_id: &str, player_id: &Uuid) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(game_id.as_bytes());
+    mac.update(player_id.as_bytes());
+    format!("{}.{}", player_id, hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Return the player id carried by `token` if it was signed for `game_id` with `secret`.
+pub fn verify_session_token(secret: &str, game_id: &str, token: &str) -> Option<Uuid> {
+    let (player_id, signature) = token.split_once('.')?;
+    let player_id = Uuid::parse_str(player_id).ok()?;
+    let signature = hex::decode(signature).ok()?;
+
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(game_id.as_bytes());
+    mac.update(player_id.as_bytes());
+    mac.verify_slice(&signature).ok()?;
+    Some(player_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{constant_time_eq, hash_password, sign_session_token, verify_password, verify_session_token};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_session_token() {
+        let player_id = Uuid::new_v4();
+        let token = sign_session_token("secret", "game", &player_id);
+
+        assert_eq!(verify_session_token("secret", "game", &token), Some(player_id));
+        assert_eq!(verify_session_token("secret", "other_game", &token), None);
+        assert_eq!(verify_session_token("other_secret", "game", &token), None);
+
+        let forged = token.replacen(&player_id.to_string(), &Uuid::new_v4().to_string(), 1);
+        assert_eq!(verify_session_token("secret", "game", &forged), None);
+    }
+
+    #[test]
+    fn test_hash_password() {
+        let hash = hash_password("hunter2");
+        assert!(verify_password(&hash, "hunter2"));
+        assert!(!verify_password(&hash, "hunter3"));
+        assert!(!verify_password("hunter2", "hunter2"));
+        // Every hash gets its own salt.
+        assert_ne!(hash, hash_password("hunter2"));
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq("secret", "secret"));
+        assert!(!constant_time_eq("secret", "secreT"));
+        assert!(!constant_time_eq("secret", "secret2"));
+    }
 }
\ No newline at end of file